@@ -0,0 +1,318 @@
+use std::fmt;
+
+use crate::expr::parse_expr_list;
+use crate::{parse_expr, Expr, ExprError, UnaryOp};
+
+/// The meaning of an `@action:` command.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ActionKind {
+    /// `set x = expr`
+    Set { variable: String, value: Expr },
+    /// `add x expr`, `x += expr` or `x -= expr` (stored with a negated amount).
+    Add { variable: String, amount: Expr },
+    /// `unset x`
+    Unset { variable: String },
+    /// `call name(arg, ...)`
+    Call { name: String, args: Vec<Expr> },
+    /// Any other command: `name` is its first word and `args` the rest of the
+    /// line, left for the host engine to interpret.
+    Custom { name: String, args: String },
+}
+
+/// Writes the action as a canonical command that `parse_action` reads back
+/// into the same value. `add` is written in its `+=` / `-=` form.
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionKind::Set { variable, value } => write!(f, "set {} = {}", variable, value),
+            ActionKind::Add {
+                variable,
+                amount:
+                    Expr::Unary {
+                        op: UnaryOp::Neg,
+                        expr,
+                    },
+            } => write!(f, "{} -= {}", variable, expr),
+            ActionKind::Add { variable, amount } => write!(f, "{} += {}", variable, amount),
+            ActionKind::Unset { variable } => write!(f, "unset {}", variable),
+            ActionKind::Call { name, args } => {
+                write!(f, "call {}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            ActionKind::Custom { name, args } if args.is_empty() => f.write_str(name),
+            ActionKind::Custom { name, args } => write!(f, "{} {}", name, args),
+        }
+    }
+}
+
+/// Parses an action command such as `set help_requested = 1`.
+///
+/// Commands starting with `set`, `add`, `unset` or `call`, and commands of
+/// the form `x += expr` or `x -= expr`, must follow the built-in grammar.
+/// Anything else, including an empty command, becomes
+/// `ActionKind::Custom`. Error offsets are byte offsets into `command`.
+pub fn parse_action(command: &str) -> Result<ActionKind, ExprError> {
+    let (name, rest) = split_word(command);
+    match name {
+        "set" => {
+            let Some(eq) = rest.find('=') else {
+                return Err(error("Expected 'set name = value'", 0, command.len()));
+            };
+            let variable = variable(command, &rest[..eq])?;
+            let value = expr_at(command, &rest[eq + 1..])?;
+            Ok(ActionKind::Set { variable, value })
+        }
+        "add" => {
+            let (target, amount) = split_word(rest.trim_start());
+            let variable = variable(command, target)?;
+            let amount = expr_at(command, amount)?;
+            Ok(ActionKind::Add { variable, amount })
+        }
+        "unset" => Ok(ActionKind::Unset {
+            variable: variable(command, rest)?,
+        }),
+        "call" => {
+            let rest = rest.trim();
+            let (Some(open), true) = (rest.find('('), rest.ends_with(')')) else {
+                return Err(error("Expected 'call name(args)'", 0, command.len()));
+            };
+            let name = variable(command, &rest[..open])?;
+            let inner = &rest[open + 1..rest.len() - 1];
+            let args = parse_expr_list(inner).map_err(|err| shift(command, inner, err))?;
+            Ok(ActionKind::Call { name, args })
+        }
+        _ => {
+            let (target, amount, negate) = if let Some(index) = command.find("+=") {
+                (&command[..index], &command[index + 2..], false)
+            } else if let Some(index) = command.find("-=") {
+                (&command[..index], &command[index + 2..], true)
+            } else {
+                return Ok(ActionKind::Custom {
+                    name: name.to_string(),
+                    args: rest.trim().to_string(),
+                });
+            };
+            if !is_identifier(target.trim()) {
+                return Ok(ActionKind::Custom {
+                    name: name.to_string(),
+                    args: rest.trim().to_string(),
+                });
+            }
+            let mut amount = expr_at(command, amount)?;
+            if negate {
+                amount = Expr::Unary {
+                    op: UnaryOp::Neg,
+                    expr: Box::new(amount),
+                };
+            }
+            Ok(ActionKind::Add {
+                variable: target.trim().to_string(),
+                amount,
+            })
+        }
+    }
+}
+
+/// Splits off the first word; both parts are subslices of `text`.
+fn split_word(text: &str) -> (&str, &str) {
+    text.split_once(char::is_whitespace)
+        .unwrap_or((text, &text[text.len()..]))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Checks that `part`, a subslice of `command`, is a variable or function
+/// name.
+fn variable(command: &str, part: &str) -> Result<String, ExprError> {
+    let name = part.trim();
+    if is_identifier(name) {
+        return Ok(name.to_string());
+    }
+    let start = offset(command, name);
+    let message = if name.is_empty() {
+        "Expected a name".to_string()
+    } else {
+        format!("Invalid name '{}'", name)
+    };
+    Err(error(&message, start, start + name.len()))
+}
+
+/// Parses `part`, a subslice of `command`, as an expression.
+fn expr_at(command: &str, part: &str) -> Result<Expr, ExprError> {
+    parse_expr(part).map_err(|err| shift(command, part, err))
+}
+
+/// Makes an error from within `part` relative to `command`.
+fn shift(command: &str, part: &str, err: ExprError) -> ExprError {
+    let by = offset(command, part);
+    ExprError {
+        message: err.message,
+        start: err.start + by,
+        end: err.end + by,
+    }
+}
+
+fn offset(command: &str, part: &str) -> usize {
+    part.as_ptr() as usize - command.as_ptr() as usize
+}
+
+fn error(message: &str, start: usize, end: usize) -> ExprError {
+    ExprError {
+        message: message.to_string(),
+        start,
+        end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Value;
+
+    fn int(value: i64) -> Expr {
+        Expr::Literal(Value::Int(value))
+    }
+
+    #[test]
+    fn test_parse_builtin_actions() {
+        assert_eq!(
+            parse_action("set help_requested = 1").unwrap(),
+            ActionKind::Set {
+                variable: "help_requested".to_string(),
+                value: int(1),
+            }
+        );
+        assert_eq!(
+            parse_action("set helped = true").unwrap(),
+            ActionKind::Set {
+                variable: "helped".to_string(),
+                value: Expr::Literal(Value::Bool(true)),
+            }
+        );
+        assert_eq!(
+            parse_action("add gold 5").unwrap(),
+            ActionKind::Add {
+                variable: "gold".to_string(),
+                amount: int(5),
+            }
+        );
+        assert_eq!(
+            parse_action("gold += 5").unwrap(),
+            parse_action("add gold 5").unwrap()
+        );
+        assert_eq!(
+            parse_action("gold -= 2").unwrap(),
+            ActionKind::Add {
+                variable: "gold".to_string(),
+                amount: Expr::Unary {
+                    op: UnaryOp::Neg,
+                    expr: Box::new(int(2)),
+                },
+            }
+        );
+        assert_eq!(
+            parse_action("unset helped").unwrap(),
+            ActionKind::Unset {
+                variable: "helped".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_action("call give_item(\"sword\", 1)").unwrap(),
+            ActionKind::Call {
+                name: "give_item".to_string(),
+                args: vec![Expr::Literal(Value::from("sword")), int(1)],
+            }
+        );
+        assert_eq!(
+            parse_action("call fade_out()").unwrap(),
+            ActionKind::Call {
+                name: "fade_out".to_string(),
+                args: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_custom_action() {
+        assert_eq!(
+            parse_action("play_sound door.wav  loud").unwrap(),
+            ActionKind::Custom {
+                name: "play_sound".to_string(),
+                args: "door.wav  loud".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_action("shake").unwrap(),
+            ActionKind::Custom {
+                name: "shake".to_string(),
+                args: String::new(),
+            }
+        );
+        assert_eq!(
+            parse_action("").unwrap(),
+            ActionKind::Custom {
+                name: String::new(),
+                args: String::new(),
+            }
+        );
+        assert!(crate::parse("::start\n@action:\n").is_ok());
+    }
+
+    #[test]
+    fn test_parse_action_errors() {
+        let err = parse_action("set gold = 1 +").unwrap_err();
+        assert_eq!(
+            (err.message.as_str(), err.start),
+            ("Expected an expression", 14)
+        );
+        let err = parse_action("set 3x = 1").unwrap_err();
+        assert_eq!(
+            (err.message.as_str(), err.start, err.end),
+            ("Invalid name '3x'", 4, 6)
+        );
+        assert_eq!(
+            parse_action("set gold").unwrap_err().message,
+            "Expected 'set name = value'"
+        );
+        assert_eq!(
+            parse_action("call greet").unwrap_err().message,
+            "Expected 'call name(args)'"
+        );
+        assert_eq!(parse_action("call greet(a b)").unwrap_err().start, 13);
+        assert_eq!(
+            parse_action("unset").unwrap_err().message,
+            "Expected a name"
+        );
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for command in [
+            "set help_requested = 1",
+            "add gold 5 * 2",
+            "gold -= 2",
+            "unset helped",
+            "call give_item(\"sword\", 1 + 1)",
+            "call fade_out()",
+            "play_sound door.wav  loud",
+            "shake",
+        ] {
+            let kind = parse_action(command).unwrap();
+            let printed = kind.to_string();
+            assert_eq!(parse_action(&printed).unwrap(), kind, "{}", printed);
+        }
+        assert_eq!(parse_action("add gold 5").unwrap().to_string(), "gold += 5");
+    }
+}
@@ -0,0 +1,11 @@
+//! Runs the Varion language server over stdin and stdout.
+
+use std::io;
+use std::process;
+
+fn main() {
+    if let Err(err) = varion::lsp::run(io::stdin().lock(), io::stdout().lock()) {
+        eprintln!("varion-lsp: {}", err);
+        process::exit(1);
+    }
+}
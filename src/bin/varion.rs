@@ -0,0 +1,766 @@
+//! The `varion` command-line tool: checks, lints, formats, exports and
+//! summarizes Varion scripts.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use serde_json::json;
+use varion::{
+    parse_project_with_loader, Dialogue, FileLoader, ParseOptions, ProjectError, ProjectOutput,
+    Span, ValidationError,
+};
+
+const USAGE: &str = "\
+Usage: varion <command> [options] <files or directories>...
+
+Directories are searched recursively for .va and .vion files.
+
+Commands:
+  check    Parse and validate scripts, exiting with 1 on errors
+             --format human|json
+  lint     Check scripts and warn about unreachable nodes, dead ends,
+           cycles without an exit and nodes with only conditional choices,
+           exiting with 1 on errors or warnings
+             --format human|json
+  fmt      Format scripts in place
+             --check    Only list unformatted files, exiting with 1 if any
+  export   Write a single script as JSON, DOT, Mermaid or CSV
+             --format json|dot|mermaid|csv (default: json)
+             -o, --output <file>
+  stats    Count nodes, choices, actions and words
+             --format human|json
+";
+
+/// Exit code for problems found in the scripts.
+const FAILURE: i32 = 1;
+/// Exit code for bad arguments or unreadable files.
+const USAGE_ERROR: i32 = 2;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = run(&args, &mut io::stdout().lock(), &mut io::stderr().lock());
+    process::exit(code);
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    format: Option<String>,
+    check: bool,
+    output: Option<String>,
+    paths: Vec<String>,
+}
+
+/// Returns the options `command` accepts, or `None` if there is no such
+/// command.
+fn command_options(command: &str) -> Option<&'static [&'static str]> {
+    match command {
+        "check" | "lint" | "stats" => Some(&["--format"]),
+        "fmt" => Some(&["--check"]),
+        "export" => Some(&["--format", "--output"]),
+        _ => None,
+    }
+}
+
+fn parse_args(command: &str, args: &[String]) -> Result<Options, String> {
+    let allowed =
+        command_options(command).ok_or_else(|| format!("Unknown command '{}'", command))?;
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = match arg.as_str() {
+            "-o" => "--output",
+            _ if arg.starts_with("--format=") => "--format",
+            _ => arg.as_str(),
+        };
+        if matches!(name, "--format" | "--check" | "--output") && !allowed.contains(&name) {
+            return Err(format!("'{}' does not apply to '{}'", arg, command));
+        }
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for '{}'", name))
+        };
+        match arg.as_str() {
+            "--format" => options.format = Some(value(arg)?),
+            "--check" => options.check = true,
+            "-o" | "--output" => options.output = Some(value(arg)?),
+            _ if arg.starts_with("--format=") => {
+                options.format = Some(arg["--format=".len()..].to_string())
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            _ => options.paths.push(arg.clone()),
+        }
+    }
+    Ok(options)
+}
+
+/// Runs a command, returning the process exit code.
+fn run(args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let Some((command, rest)) = args.split_first() else {
+        let _ = write!(err, "{}", USAGE);
+        return USAGE_ERROR;
+    };
+    if command == "-h" || command == "--help" || command == "help" {
+        let _ = write!(out, "{}", USAGE);
+        return 0;
+    }
+    let result = parse_args(command, rest).and_then(|options| {
+        let files = collect_files(&options.paths)?;
+        match command.as_str() {
+            "check" => check(&files, &options, false, out),
+            "lint" => check(&files, &options, true, out),
+            "fmt" => format_files(&files, &options, out, err),
+            "export" => export(&files, &options, out, err),
+            "stats" => stats(&files, &options, out, err),
+            _ => Err(format!("Unknown command '{}'", command)),
+        }
+    });
+    match result {
+        Ok(code) => code,
+        Err(message) => {
+            let _ = writeln!(err, "error: {}\n\n{}", message, USAGE);
+            USAGE_ERROR
+        }
+    }
+}
+
+/// Expands directories into the `.va` and `.vion` files below them.
+fn collect_files(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    if paths.is_empty() {
+        return Err("No input files".to_string());
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            walk(path, &mut files).map_err(|e| format!("{}: {}", path.display(), e))?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("va" | "vion")
+        ) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// A problem found in a script.
+#[derive(PartialEq)]
+struct Diagnostic {
+    file: String,
+    span: Span,
+    message: String,
+    /// `"error"` or `"warning"`.
+    severity: &'static str,
+    /// `"parse"`, `"include"`, `"validation"`, or the code of a lint.
+    kind: &'static str,
+}
+
+impl Diagnostic {
+    fn human(&self) -> String {
+        format!(
+            "{}:{}:{}: {}: {}",
+            self.file, self.span.line, self.span.column, self.severity, self.message
+        )
+    }
+}
+
+/// A script together with everything it includes.
+struct Project {
+    output: ProjectOutput,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Project {
+    /// Returns the file `node` was declared in.
+    fn file_of(&self, node: &str) -> String {
+        let file = self.output.dialogue.get(node).and_then(|n| n.file.clone());
+        file.unwrap_or_default()
+    }
+
+    fn has_errors(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+}
+
+/// Parses the script at `path` and the scripts it includes, collecting
+/// every parse, include and validation problem.
+fn load(path: &Path) -> Result<Project, String> {
+    let output = parse_project_with_loader(path, &mut FileLoader, &ParseOptions::default());
+    let mut project = Project {
+        output,
+        diagnostics: Vec::new(),
+    };
+    for error in &project.output.errors {
+        if let ProjectError::Load {
+            path,
+            message,
+            included_from: None,
+        } = error
+        {
+            return Err(format!("{}: {}", path.display(), message));
+        }
+        project.diagnostics.push(Diagnostic {
+            file: error.file().display().to_string(),
+            span: error.span().unwrap_or_default(),
+            message: error.message(),
+            severity: "error",
+            kind: match error {
+                ProjectError::Parse { .. } => "parse",
+                _ => "include",
+            },
+        });
+    }
+    if let Err(errors) = project.output.dialogue.validate() {
+        for error in errors {
+            let file = match &error {
+                ValidationError::UnresolvedTarget { node, .. }
+                | ValidationError::UnresolvedNext { node, .. } => project.file_of(node),
+                ValidationError::UnresolvedStart { .. } => project
+                    .output
+                    .start_file
+                    .as_ref()
+                    .map(|file| file.display().to_string())
+                    .unwrap_or_default(),
+            };
+            project.diagnostics.push(Diagnostic {
+                file,
+                span: error.span(),
+                message: error.message(),
+                severity: "error",
+                kind: "validation",
+            });
+        }
+    }
+    Ok(project)
+}
+
+/// Loads every file as the root of a project, except files that another
+/// one includes: those are checked as part of it, where the nodes they
+/// refer to are known.
+fn load_all(files: &[PathBuf]) -> Result<Vec<Project>, String> {
+    let projects = files
+        .iter()
+        .map(|path| load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let root = |project: &Project| project.output.files.last().cloned();
+    let included: HashSet<PathBuf> = projects
+        .iter()
+        .flat_map(|project| {
+            let files = &project.output.files;
+            files[..files.len().saturating_sub(1)].iter().cloned()
+        })
+        .collect();
+
+    let (mut kept, rest): (Vec<Project>, Vec<Project>) = projects
+        .into_iter()
+        .partition(|project| root(project).is_none_or(|root| !included.contains(&root)));
+    // Scripts that include each other have no outside root; keep the first
+    // of each such group.
+    for project in rest {
+        let covered = kept
+            .iter()
+            .any(|kept| root(&project).is_some_and(|root| kept.output.files.contains(&root)));
+        if !covered {
+            kept.push(project);
+        }
+    }
+    Ok(kept)
+}
+
+/// Loads a project, reporting its problems to `err`. Returns `None` if it
+/// has any.
+fn load_clean(path: &Path, err: &mut dyn Write) -> Result<Option<Dialogue>, String> {
+    let project = load(path)?;
+    for diagnostic in &project.diagnostics {
+        let _ = writeln!(err, "{}", diagnostic.human());
+    }
+    Ok((!project.has_errors()).then_some(project.output.dialogue))
+}
+
+/// Reports the problems in every file, and with `lint` also the lints of
+/// projects without errors.
+fn check(
+    files: &[PathBuf],
+    options: &Options,
+    lint: bool,
+    out: &mut dyn Write,
+) -> Result<i32, String> {
+    let projects = load_all(files)?;
+    let mut files_read = HashSet::new();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for mut project in projects {
+        files_read.extend(project.output.files.iter().cloned());
+        if lint && !project.has_errors() {
+            for lint in project.output.dialogue.lint() {
+                let file = project.file_of(lint.node());
+                project.diagnostics.push(Diagnostic {
+                    file,
+                    span: lint.span(),
+                    message: lint.message(),
+                    severity: "warning",
+                    kind: lint.code(),
+                });
+            }
+        }
+        // Scripts included from several projects are reported once.
+        for diagnostic in project.diagnostics {
+            if !diagnostics.contains(&diagnostic) {
+                diagnostics.push(diagnostic);
+            }
+        }
+    }
+    match options.format.as_deref().unwrap_or("human") {
+        "human" => {
+            for diagnostic in &diagnostics {
+                let _ = writeln!(out, "{}", diagnostic.human());
+            }
+            let errors = diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == "error")
+                .count();
+            if lint {
+                let _ = writeln!(
+                    out,
+                    "Linted {} file(s): {} error(s), {} warning(s).",
+                    files_read.len(),
+                    errors,
+                    diagnostics.len() - errors
+                );
+            } else {
+                let _ = writeln!(
+                    out,
+                    "Checked {} file(s): {} error(s).",
+                    files_read.len(),
+                    errors
+                );
+            }
+        }
+        "json" => {
+            let diagnostics: Vec<_> = diagnostics
+                .iter()
+                .map(|d| {
+                    json!({
+                        "file": d.file,
+                        "line": d.span.line,
+                        "column": d.span.column,
+                        "start": d.span.start,
+                        "end": d.span.end,
+                        "severity": d.severity,
+                        "kind": d.kind,
+                        "message": d.message,
+                    })
+                })
+                .collect();
+            let report = json!({ "files": files_read.len(), "diagnostics": diagnostics });
+            let _ = writeln!(out, "{}", report);
+        }
+        other => return Err(format!("Unknown check format '{}'", other)),
+    }
+    Ok(if diagnostics.is_empty() { 0 } else { FAILURE })
+}
+
+fn format_files(
+    files: &[PathBuf],
+    options: &Options,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<i32, String> {
+    let mut code = 0;
+    for path in files {
+        let source = read(path)?;
+        let formatted = match varion::fmt::format(&source) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                let _ = writeln!(
+                    err,
+                    "{}:{}:{}: error: {}",
+                    path.display(),
+                    error.line(),
+                    error.column(),
+                    error.message()
+                );
+                code = FAILURE;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if options.check {
+            let _ = writeln!(out, "{}", path.display());
+            code = FAILURE;
+        } else {
+            fs::write(path, formatted).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+    }
+    Ok(code)
+}
+
+fn export(
+    files: &[PathBuf],
+    options: &Options,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<i32, String> {
+    let [path] = files else {
+        return Err("export takes exactly one file".to_string());
+    };
+    let Some(dialogue) = load_clean(path, err)? else {
+        return Ok(FAILURE);
+    };
+    let text = match options.format.as_deref().unwrap_or("json") {
+        "json" => serde_json::to_string_pretty(&dialogue).map_err(|e| e.to_string())? + "\n",
+        "dot" => dialogue.to_dot(),
+        "mermaid" => dialogue.to_mermaid(),
+        "csv" => to_csv(&dialogue),
+        other => return Err(format!("Unknown export format '{}'", other)),
+    };
+    match &options.output {
+        Some(output) => fs::write(output, text).map_err(|e| format!("{}: {}", output, e))?,
+        None => {
+            let _ = out.write_all(text.as_bytes());
+        }
+    }
+    Ok(0)
+}
+
+/// One row per body, choice and `@next:`, in declaration order.
+fn to_csv(dialogue: &Dialogue) -> String {
+    let mut rows = vec![["node", "type", "text", "target", "condition"].map(String::from)];
+    for node in dialogue.iter() {
+        let row = |kind: &str, text: &str, target: &str, condition: &str| {
+            [&node.name, kind, text, target, condition].map(String::from)
+        };
+        if !node.body.is_empty() {
+            rows.push(row("body", &node.body, "", ""));
+        }
+        for choice in &node.choices {
+            let condition = choice.condition.as_deref().unwrap_or_default();
+            rows.push(row("choice", &choice.text, &choice.target_node, condition));
+        }
+        if let Some(next) = &node.next {
+            rows.push(row("next", "", next, ""));
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            fields.join(",") + "\n"
+        })
+        .collect()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn stats(
+    files: &[PathBuf],
+    options: &Options,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<i32, String> {
+    let mut code = 0;
+    let (mut nodes, mut choices, mut conditional, mut actions, mut endings, mut words) =
+        (0, 0, 0, 0, 0, 0);
+    let mut files_read = HashSet::new();
+    // Scripts included from several projects are counted once.
+    let mut counted = HashSet::new();
+    for project in load_all(files)? {
+        if project.has_errors() {
+            for diagnostic in &project.diagnostics {
+                let _ = writeln!(err, "{}", diagnostic.human());
+            }
+            code = FAILURE;
+            continue;
+        }
+        files_read.extend(project.output.files.iter().cloned());
+        let dialogue = &project.output.dialogue;
+        for node in dialogue.iter() {
+            if !counted.insert((node.file.clone(), node.name.clone())) {
+                continue;
+            }
+            nodes += 1;
+            actions += node.actions.len();
+            choices += node.choices.len();
+            conditional += node
+                .choices
+                .iter()
+                .filter(|choice| choice.condition.is_some())
+                .count();
+            if node.choices.is_empty() && node.next.is_none() {
+                endings += 1;
+            }
+            words += node.body.split_whitespace().count();
+            words += node
+                .choices
+                .iter()
+                .map(|choice| choice.text.split_whitespace().count())
+                .sum::<usize>();
+        }
+    }
+    let counts = [
+        ("files", files_read.len()),
+        ("nodes", nodes),
+        ("choices", choices),
+        ("conditional_choices", conditional),
+        ("actions", actions),
+        ("endings", endings),
+        ("words", words),
+    ];
+    match options.format.as_deref().unwrap_or("human") {
+        "human" => {
+            for (name, count) in counts {
+                let _ = writeln!(out, "{:<20} {}", name.replace('_', " "), count);
+            }
+        }
+        "json" => {
+            let report: serde_json::Map<String, serde_json::Value> = counts
+                .iter()
+                .map(|(name, count)| (name.to_string(), json!(count)))
+                .collect();
+            let _ = writeln!(out, "{}", serde_json::Value::Object(report));
+        }
+        other => return Err(format!("Unknown stats format '{}'", other)),
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("varion-cli-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> String {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn varion(args: &[&str]) -> (i32, String, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let code = run(&args, &mut out, &mut err);
+        (
+            code,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = TempDir::new("check");
+        dir.write("good.va", "::start\n* Go => end\n::end\n");
+        let bad = dir.write("scenes/bad.vion", "::start\n* Go => nd\n::end\n");
+        dir.write("notes.txt", "not a script");
+        let root = dir.0.display().to_string();
+
+        let (code, out, _) = varion(&["check", &root]);
+        assert_eq!(code, FAILURE);
+        assert_eq!(
+            out,
+            format!(
+                "{}:2:9: error: Choice in node 'start' targets unknown node 'nd'. Did you mean 'end'?\n\
+                 Checked 2 file(s): 1 error(s).\n",
+                bad
+            )
+        );
+
+        let (code, out, _) = varion(&["check", "--format", "json", &bad]);
+        assert_eq!(code, FAILURE);
+        let report: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(report["files"], 1);
+        assert_eq!(report["diagnostics"][0]["kind"], "validation");
+        assert_eq!(report["diagnostics"][0]["line"], 2);
+
+        let good = dir.0.join("good.va").display().to_string();
+        assert_eq!(varion(&["check", &good]).0, 0);
+        assert_eq!(varion(&["check"]).0, USAGE_ERROR);
+        assert_eq!(varion(&["check", "--bogus", &good]).0, USAGE_ERROR);
+        assert_eq!(varion(&["frobnicate", &good]).0, USAGE_ERROR);
+        let (code, _, err) = varion(&["check", "--check", &good]);
+        assert_eq!(code, USAGE_ERROR);
+        assert!(err.starts_with("error: '--check' does not apply to 'check'"));
+        assert_eq!(varion(&["stats", "-o", "out.json", &good]).0, USAGE_ERROR);
+        assert_eq!(varion(&["fmt", "--format=json", &good]).0, USAGE_ERROR);
+    }
+
+    #[test]
+    fn test_check_follows_includes() {
+        let dir = TempDir::new("include");
+        let main = dir.write(
+            "main.va",
+            "@include \"scenes/shop.va\"\n@include \"missing.va\"\n\n::hub\n* Shop => shop\n",
+        );
+        dir.write("scenes/shop.va", "::shop\n@next: hub\n");
+        let root = dir.0.display().to_string();
+
+        // shop.va is only checked as part of main.va, where `hub` exists.
+        let (code, out, _) = varion(&["check", &root]);
+        assert_eq!(code, FAILURE);
+        let missing = dir.0.join("missing.va");
+        assert!(out.starts_with(&format!(
+            "{}:2:1: error: Cannot read '{}': ",
+            main,
+            missing.display()
+        )));
+        assert!(out.ends_with("\nChecked 2 file(s): 1 error(s).\n"));
+
+        fs::write(
+            &main,
+            "@include \"scenes/shop.va\"\n\n::hub\n* Shop => shop\n",
+        )
+        .unwrap();
+        let (code, out, _) = varion(&["stats", "--format", "json", &root]);
+        assert_eq!(code, 0);
+        let stats: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!((&stats["files"], &stats["nodes"]), (&json!(2), &json!(2)));
+    }
+
+    #[test]
+    fn test_lint() {
+        let dir = TempDir::new("lint");
+        let script = dir.write(
+            "scene.va",
+            "::start\n* Go => end\n\n::end\n#end\n\n::orphan\n@next: end\n",
+        );
+
+        let (code, out, _) = varion(&["lint", &script]);
+        assert_eq!(code, FAILURE);
+        assert_eq!(
+            out,
+            format!(
+                "{}:7:3: warning: Node 'orphan' cannot be reached from the entry node.\n\
+                 Linted 1 file(s): 0 error(s), 1 warning(s).\n",
+                script
+            )
+        );
+
+        let (_, out, _) = varion(&["lint", "--format", "json", &script]);
+        let report: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(report["diagnostics"][0]["severity"], "warning");
+        assert_eq!(report["diagnostics"][0]["kind"], "unreachable");
+
+        // `check` does not lint.
+        assert_eq!(varion(&["check", &script]).0, 0);
+    }
+
+    #[test]
+    fn test_fmt() {
+        let dir = TempDir::new("fmt");
+        let messy = dir.write("messy.va", "::start\n*Go=>start\n@who:Guard\n");
+        let tidy = dir.write("tidy.va", "::start\n");
+
+        let (code, out, _) = varion(&["fmt", "--check", &messy, &tidy]);
+        assert_eq!((code, out), (FAILURE, format!("{}\n", messy)));
+
+        assert_eq!(varion(&["fmt", &messy, &tidy]).0, 0);
+        assert_eq!(
+            fs::read_to_string(&messy).unwrap(),
+            "::start\n@who: Guard\n* Go => start\n"
+        );
+        assert_eq!(varion(&["fmt", "--check", &messy]).0, 0);
+
+        let broken = dir.write("broken.va", "::start\n* broken\n");
+        let (code, _, err) = varion(&["fmt", &broken]);
+        assert_eq!(code, FAILURE);
+        assert!(err.starts_with(&format!("{}:2:1: error:", broken)));
+    }
+
+    #[test]
+    fn test_export() {
+        let dir = TempDir::new("export");
+        let script = dir.write(
+            "scene.va",
+            "::start\nHello, \"you\".\n* Go => end @if a > 1\n::end\n@next: start\n",
+        );
+
+        let (code, out, _) = varion(&["export", &script]);
+        assert_eq!(code, 0);
+        let dialogue: Dialogue = serde_json::from_str(&out).unwrap();
+        assert_eq!(dialogue.order(), ["start", "end"]);
+
+        let (_, out, _) = varion(&["export", "--format=dot", &script]);
+        assert!(out.contains("\"start\" -> \"end\" [label=\"Go\\n[a > 1]\"];"));
+
+        let (_, out, _) = varion(&["export", "--format", "mermaid", &script]);
+        assert!(out.starts_with("flowchart TD\n"));
+
+        let output = dir.0.join("scene.csv").display().to_string();
+        assert_eq!(
+            varion(&["export", "--format", "csv", "-o", &output, &script]).0,
+            0
+        );
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "node,type,text,target,condition\n\
+             start,body,\"Hello, \"\"you\"\".\",,\n\
+             start,choice,Go,end,a > 1\n\
+             end,next,,start,\n"
+        );
+
+        assert_eq!(varion(&["export", &script, &script]).0, USAGE_ERROR);
+        assert_eq!(
+            varion(&["export", "--format", "xml", &script]).0,
+            USAGE_ERROR
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let (code, out, _) = varion(&["stats", "--format", "json", "examples"]);
+        assert_eq!(code, 0);
+        let stats: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(stats["files"], 2);
+        assert!(stats["nodes"].as_u64().unwrap() > 0);
+
+        let (_, out, _) = varion(&["stats", "examples/varion_long_example.vion"]);
+        assert!(out.contains("nodes                4\n"));
+    }
+}
@@ -0,0 +1,295 @@
+use std::fmt;
+
+use crate::parser::lower;
+use crate::{ParseOptions, ParseOutput, Span};
+
+/// What a line of source is, syntactically.
+///
+/// Spans point into the script, like every other `Span`. Lines are
+/// classified on their own; whether a line is allowed where it appears is
+/// decided when the tree is turned into a `Dialogue`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    /// An empty or whitespace-only line.
+    Blank,
+    /// A `//` comment.
+    Comment,
+    /// `::name`. The name may be empty.
+    Header { name: Span },
+    /// `@if condition`, applying to the next choice.
+    If { condition: Span },
+    /// `@include "path"`. `path` is the text between the quotes, or `None`
+    /// if there is no quoted path.
+    Include { path: Option<Span> },
+    /// `@namespace name`. `name` is `None` if it is missing or contains
+    /// whitespace.
+    Namespace { name: Option<Span> },
+    /// `@key: value`, including `@action:`, `@next:` and `@start:`.
+    /// `value` is `None` if the colon is missing.
+    Directive { key: Span, value: Option<Span> },
+    /// A line of `#tags`; one span per non-empty tag, including its `#`.
+    Tags { tags: Vec<Span> },
+    /// `* text => target @if condition`. Without exactly one `=>`, `target`
+    /// is `None` and `text` covers everything after the `*`.
+    Choice {
+        text: Span,
+        target: Option<Span>,
+        condition: Option<Span>,
+    },
+    /// Any other line: dialogue text.
+    Body,
+}
+
+/// A single source line, with its line ending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CstLine {
+    pub kind: LineKind,
+    /// The line as written, without its ending.
+    pub text: String,
+    /// `"\n"`, `"\r\n"`, or empty for a last line without one.
+    pub ending: String,
+    /// The whole line, without its ending.
+    pub span: Span,
+    /// The line without surrounding whitespace.
+    pub content: Span,
+}
+
+impl CstLine {
+    /// Returns the text covered by `span`, which must lie within the line.
+    pub fn slice(&self, span: Span) -> &str {
+        let start = span.start - self.span.start;
+        &self.text[start..start + (span.end - span.start)]
+    }
+}
+
+/// A `::name` line and every line up to the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CstNode {
+    pub header: CstLine,
+    pub lines: Vec<CstLine>,
+}
+
+/// A lossless concrete syntax tree of a Varion script.
+///
+/// Every byte of the source, including comments, whitespace and line
+/// endings, is kept: `cst.to_string()` gives back the exact script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cst {
+    /// The lines before the first `::` declaration.
+    pub preamble: Vec<CstLine>,
+    pub nodes: Vec<CstNode>,
+}
+
+impl Cst {
+    /// Iterates over every line in source order.
+    pub fn lines(&self) -> impl Iterator<Item = &CstLine> {
+        self.preamble.iter().chain(
+            self.nodes
+                .iter()
+                .flat_map(|node| std::iter::once(&node.header).chain(&node.lines)),
+        )
+    }
+
+    /// Builds the `Dialogue`, as `parse_with_diagnostics` does.
+    pub fn to_dialogue(&self) -> ParseOutput {
+        self.to_dialogue_with_options(&ParseOptions::default())
+    }
+
+    /// Builds the `Dialogue` with the given options.
+    pub fn to_dialogue_with_options(&self, options: &ParseOptions) -> ParseOutput {
+        lower(self, options)
+    }
+}
+
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in self.lines() {
+            f.write_str(&line.text)?;
+            f.write_str(&line.ending)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a script into a lossless concrete syntax tree.
+///
+/// This never fails: syntax errors are reported when the tree is turned
+/// into a `Dialogue`.
+pub fn parse_cst(script: &str) -> Cst {
+    let mut cst = Cst::default();
+    let mut offset = 0;
+    for (num, raw) in script.split_inclusive('\n').enumerate() {
+        let text = raw.strip_suffix('\n').unwrap_or(raw);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let line = classify(num, offset, text, &raw[text.len()..]);
+        offset += raw.len();
+        match (&line.kind, cst.nodes.last_mut()) {
+            (LineKind::Header { .. }, _) => cst.nodes.push(CstNode {
+                header: line,
+                lines: Vec::new(),
+            }),
+            (_, Some(node)) => node.lines.push(line),
+            (_, None) => cst.preamble.push(line),
+        }
+    }
+    cst
+}
+
+fn classify(num: usize, start: usize, text: &str, ending: &str) -> CstLine {
+    // Builds the span of `part`, which must be a subslice of `text`.
+    let span = |part: &str| {
+        let offset = part.as_ptr() as usize - text.as_ptr() as usize;
+        Span {
+            line: num + 1,
+            column: text[..offset].chars().count() + 1,
+            start: start + offset,
+            end: start + offset + part.len(),
+        }
+    };
+    let trimmed = text.trim();
+
+    let kind = if trimmed.is_empty() {
+        LineKind::Blank
+    } else if trimmed.starts_with("//") {
+        LineKind::Comment
+    } else if let Some(name) = trimmed.strip_prefix("::") {
+        LineKind::Header {
+            name: span(name.trim()),
+        }
+    } else if let Some(condition) = trimmed.strip_prefix("@if") {
+        LineKind::If {
+            condition: span(condition.trim()),
+        }
+    } else if let Some(rest) = keyword_argument(trimmed, "@include") {
+        let path = rest
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .filter(|path| !path.is_empty());
+        LineKind::Include {
+            path: path.map(span),
+        }
+    } else if let Some(rest) = keyword_argument(trimmed, "@namespace") {
+        let name =
+            Some(rest).filter(|name| !name.is_empty() && !name.contains(char::is_whitespace));
+        LineKind::Namespace {
+            name: name.map(span),
+        }
+    } else if let Some(directive) = trimmed.strip_prefix('@') {
+        match directive.split_once(':') {
+            Some((key, value)) => LineKind::Directive {
+                key: span(key.trim()),
+                value: Some(span(value.trim())),
+            },
+            None => LineKind::Directive {
+                key: span(directive.trim()),
+                value: None,
+            },
+        }
+    } else if trimmed.starts_with('#') {
+        LineKind::Tags {
+            tags: trimmed
+                .split_whitespace()
+                .filter(|tag| *tag != "#")
+                .map(span)
+                .collect(),
+        }
+    } else if let Some(choice) = trimmed.strip_prefix('*') {
+        let parts: Vec<&str> = choice.split("=>").map(str::trim).collect();
+        match parts[..] {
+            [text, rest] => {
+                let (target, condition) = match rest.find("@if") {
+                    Some(index) => (rest[..index].trim(), Some(rest[index + 3..].trim())),
+                    None => (rest, None),
+                };
+                LineKind::Choice {
+                    text: span(text),
+                    target: Some(span(target)),
+                    condition: condition.map(span),
+                }
+            }
+            _ => LineKind::Choice {
+                text: span(choice),
+                target: None,
+                condition: None,
+            },
+        }
+    } else {
+        LineKind::Body
+    };
+
+    CstLine {
+        kind,
+        text: text.to_string(),
+        ending: ending.to_string(),
+        span: span(text),
+        content: span(trimmed),
+    }
+}
+
+/// Returns what follows `keyword`, trimmed, if `line` starts with it as a
+/// word. An `@include:` or `@namespace:` line is meta like any other
+/// `@key:`.
+fn keyword_argument<'a>(line: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(keyword)?;
+    match rest.chars().next() {
+        None => Some(rest),
+        Some(c) if c.is_whitespace() || c == '"' => Some(rest.trim()),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_with_diagnostics;
+    use std::fs;
+
+    #[test]
+    fn test_cst_is_lossless() {
+        let script = "// intro\r\n@start: a\n\n::a  \n  Hi there.  \n// note\n* Go =>  b @if x > 1\n#t1 # #t2\n\t\n::b\nEnd.";
+        let cst = parse_cst(script);
+        assert_eq!(cst.to_string(), script);
+        assert_eq!(cst.preamble.len(), 3);
+        assert_eq!(cst.nodes.len(), 2);
+        assert_eq!(cst.preamble[0].ending, "\r\n");
+        assert_eq!(cst.nodes[1].lines[0].ending, "");
+
+        let node = &cst.nodes[0];
+        assert_eq!(node.header.slice(node.header.content), "::a");
+        let kinds: Vec<_> = node.lines.iter().map(|line| &line.kind).collect();
+        assert!(matches!(kinds[0], LineKind::Body));
+        assert!(matches!(kinds[1], LineKind::Comment));
+        assert!(matches!(kinds[4], LineKind::Blank));
+        let line = &node.lines[2];
+        let LineKind::Choice {
+            text,
+            target: Some(target),
+            condition: Some(condition),
+        } = line.kind
+        else {
+            panic!("expected a choice, got {:?}", line.kind);
+        };
+        assert_eq!(line.slice(text), "Go");
+        assert_eq!(line.slice(target), "b");
+        assert_eq!(line.slice(condition), "x > 1");
+        assert_eq!((target.line, target.column), (7, 10));
+        let LineKind::Tags { tags } = &node.lines[3].kind else {
+            panic!("expected tags");
+        };
+        let tags: Vec<_> = tags.iter().map(|tag| node.lines[3].slice(*tag)).collect();
+        assert_eq!(tags, vec!["#t1", "#t2"]);
+    }
+
+    #[test]
+    fn test_cst_builds_the_same_dialogue() {
+        for path in [
+            "examples/varion_examples.va",
+            "examples/varion_long_example.vion",
+        ] {
+            let script = fs::read_to_string(path).unwrap();
+            let cst = parse_cst(&script);
+            assert_eq!(cst.to_string(), script);
+            assert_eq!(cst.to_dialogue(), parse_with_diagnostics(&script));
+        }
+    }
+}
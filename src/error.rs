@@ -0,0 +1,220 @@
+use std::error::Error;
+use std::fmt;
+
+/// A location in the source script.
+///
+/// `line` and `column` are 1-based (the column counts characters), while
+/// `start` and `end` are byte offsets into the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Returns the byte range covered by this span.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    /// Returns the span of `text[start..end]`, where `text` is the
+    /// single-line source covered by this span.
+    pub(crate) fn narrow(&self, text: &str, start: usize, end: usize) -> Span {
+        Span {
+            line: self.line,
+            column: self.column + text[..start].chars().count(),
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+}
+
+/// An error produced while parsing a Varion script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A `::` declaration without a node name.
+    EmptyNodeName { span: Span },
+    /// An `@if` line that is not followed by a choice in the same node.
+    DanglingIf { span: Span },
+    /// An `@if` line before the first node declaration.
+    IfOutsideNode { span: Span },
+    /// Two `@if` lines in a row.
+    ConsecutiveIf { span: Span },
+    /// An `@if` line followed by a meta or action line.
+    IfBeforeDirective { span: Span },
+    /// An `@if` line followed by body text.
+    IfBeforeBody { span: Span },
+    /// A second `@next` directive in the same node.
+    DuplicateNext { span: Span },
+    /// An `@next` directive in a node that already has choices.
+    NextWithChoices { span: Span },
+    /// A choice in a node that already has an `@next` directive.
+    ChoiceWithNext { span: Span },
+    /// An `@` line that is neither an action, `@next` nor `@key: value` meta.
+    InvalidDirective { line: String, span: Span },
+    /// A choice line that does not have the `* text => target` shape.
+    InvalidChoiceFormat { choice: String, span: Span },
+    /// A choice with both a preceding `@if` and an inline `@if`.
+    ConflictingConditions { span: Span },
+    /// A line that appears before the first node declaration.
+    ContentOutsideNode { span: Span },
+    /// An `@include` line without a quoted path.
+    InvalidInclude { span: Span },
+    /// An `@include` line after the first node declaration.
+    IncludeInsideNode { span: Span },
+    /// An `@namespace` line without a name, or with whitespace in it.
+    InvalidNamespace { span: Span },
+    /// A second `@namespace` line.
+    DuplicateNamespace { span: Span },
+    /// An `@namespace` line after the first node declaration.
+    NamespaceInsideNode { span: Span },
+    /// A node declared with the same name as an earlier node.
+    DuplicateNode {
+        name: String,
+        /// The header of the earlier declaration.
+        first: Span,
+        span: Span,
+    },
+    /// A second `@start` directive.
+    DuplicateStart { span: Span },
+    /// An `@if` condition that is not a valid expression.
+    InvalidCondition { message: String, span: Span },
+    /// A built-in `@action:` command (`set`, `add`, `unset`, `call`, `+=`,
+    /// `-=`) with invalid syntax.
+    InvalidAction { message: String, span: Span },
+    /// A `{expr}` substitution, `{if}` conditional or `{~...}` alternative
+    /// in body or choice text that is unclosed or not valid.
+    InvalidInterpolation { message: String, span: Span },
+}
+
+impl ParseError {
+    /// Returns the location the error refers to.
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::EmptyNodeName { span }
+            | ParseError::DanglingIf { span }
+            | ParseError::IfOutsideNode { span }
+            | ParseError::ConsecutiveIf { span }
+            | ParseError::IfBeforeDirective { span }
+            | ParseError::IfBeforeBody { span }
+            | ParseError::DuplicateNext { span }
+            | ParseError::NextWithChoices { span }
+            | ParseError::ChoiceWithNext { span }
+            | ParseError::InvalidDirective { span, .. }
+            | ParseError::InvalidChoiceFormat { span, .. }
+            | ParseError::ConflictingConditions { span }
+            | ParseError::ContentOutsideNode { span }
+            | ParseError::InvalidInclude { span }
+            | ParseError::IncludeInsideNode { span }
+            | ParseError::InvalidNamespace { span }
+            | ParseError::DuplicateNamespace { span }
+            | ParseError::NamespaceInsideNode { span }
+            | ParseError::DuplicateNode { span, .. }
+            | ParseError::DuplicateStart { span }
+            | ParseError::InvalidCondition { span, .. }
+            | ParseError::InvalidAction { span, .. }
+            | ParseError::InvalidInterpolation { span, .. } => *span,
+        }
+    }
+
+    /// Returns the 1-based line the error refers to.
+    pub fn line(&self) -> usize {
+        self.span().line
+    }
+
+    /// Returns the 1-based column the error refers to.
+    pub fn column(&self) -> usize {
+        self.span().column
+    }
+
+    /// Returns the error message without location information.
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::EmptyNodeName { .. } => {
+                "Node declaration '::' must be followed by a name.".to_string()
+            }
+            ParseError::DanglingIf { .. } => {
+                "Dangling @if condition is not followed by a choice.".to_string()
+            }
+            ParseError::IfOutsideNode { .. } => {
+                "@if condition found outside of a node.".to_string()
+            }
+            ParseError::ConsecutiveIf { .. } => {
+                "Consecutive @if conditions are not allowed.".to_string()
+            }
+            ParseError::IfBeforeDirective { .. } => {
+                "@if must be immediately followed by a choice, not a meta/action line.".to_string()
+            }
+            ParseError::IfBeforeBody { .. } => {
+                "@if must be immediately followed by a choice, not body text.".to_string()
+            }
+            ParseError::DuplicateNext { .. } => "Duplicate @next directive found.".to_string(),
+            ParseError::NextWithChoices { .. } => {
+                "@next cannot be used in a node that already has choices.".to_string()
+            }
+            ParseError::ChoiceWithNext { .. } => {
+                "Choices cannot be added to a node that has a @next directive.".to_string()
+            }
+            ParseError::InvalidDirective { line, .. } => {
+                format!("Invalid meta or action line: {}", line)
+            }
+            ParseError::InvalidChoiceFormat { choice, .. } => {
+                format!("Invalid choice format: {}", choice)
+            }
+            ParseError::ConflictingConditions { .. } => {
+                "A choice cannot have both a preceding @if and an inline @if.".to_string()
+            }
+            ParseError::ContentOutsideNode { .. } => {
+                "Content found outside of a node declaration. Every line must belong to a node starting with '::'.".to_string()
+            }
+            ParseError::InvalidInclude { .. } => {
+                "@include must be followed by a quoted path, like @include \"scenes/intro.va\"."
+                    .to_string()
+            }
+            ParseError::IncludeInsideNode { .. } => {
+                "@include must appear before the first node declaration.".to_string()
+            }
+            ParseError::InvalidNamespace { .. } => {
+                "@namespace must be followed by a name without spaces.".to_string()
+            }
+            ParseError::DuplicateNamespace { .. } => {
+                "Duplicate @namespace directive found.".to_string()
+            }
+            ParseError::NamespaceInsideNode { .. } => {
+                "@namespace must appear before the first node declaration.".to_string()
+            }
+            ParseError::DuplicateNode { name, first, .. } => format!(
+                "Node '{}' is already declared on line {}.",
+                name, first.line
+            ),
+            ParseError::DuplicateStart { .. } => "Duplicate @start directive found.".to_string(),
+            ParseError::InvalidCondition { message, .. } => {
+                format!("Invalid @if condition: {}.", message)
+            }
+            ParseError::InvalidAction { message, .. } => {
+                format!("Invalid @action: {}.", message)
+            }
+            ParseError::InvalidInterpolation { message, .. } => {
+                format!("Invalid text substitution: {}.", message)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(
+            f,
+            "Error on line {}, column {}: {}",
+            span.line,
+            span.column,
+            self.message()
+        )
+    }
+}
+
+impl Error for ParseError {}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::{parse_expr, BinaryOp, Choice, Expr, ExprError, UnaryOp, Value};
+
+/// Provides variable values to the evaluator.
+///
+/// Implement this for a game's own state to evaluate conditions against it
+/// directly; `HashMap<String, Value>` works out of the box.
+pub trait VariableStore {
+    /// Returns the value of `name`, or `None` if it is not defined.
+    fn get(&self, name: &str) -> Option<Value>;
+}
+
+impl VariableStore for HashMap<String, Value> {
+    fn get(&self, name: &str) -> Option<Value> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+/// An error produced while evaluating an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// The expression source could not be parsed.
+    Syntax(ExprError),
+    /// A variable that the store does not define.
+    UnknownVariable(String),
+    /// An operator applied to values of the wrong type.
+    TypeMismatch {
+        op: &'static str,
+        left: &'static str,
+        /// The right operand's type, for binary operators.
+        right: Option<&'static str>,
+    },
+    /// A condition that evaluated to something other than a bool.
+    ConditionNotBool {
+        found: &'static str,
+    },
+    DivisionByZero,
+    /// Integer arithmetic that does not fit in an `i64`.
+    Overflow,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Syntax(err) => write!(f, "Syntax error: {}", err),
+            EvalError::UnknownVariable(name) => write!(f, "Unknown variable '{}'", name),
+            EvalError::TypeMismatch {
+                op,
+                left,
+                right: Some(right),
+            } => write!(f, "Cannot apply '{}' to {} and {}", op, left, right),
+            EvalError::TypeMismatch {
+                op,
+                left,
+                right: None,
+            } => write!(f, "Cannot apply '{}' to {}", op, left),
+            EvalError::ConditionNotBool { found } => {
+                write!(f, "Condition must be a bool, found {}", found)
+            }
+            EvalError::DivisionByZero => write!(f, "Division by zero"),
+            EvalError::Overflow => write!(f, "Integer overflow"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+impl From<ExprError> for EvalError {
+    fn from(err: ExprError) -> Self {
+        EvalError::Syntax(err)
+    }
+}
+
+/// Parses and evaluates an expression such as `reputation < 3`.
+pub fn evaluate_condition(source: &str, vars: &impl VariableStore) -> Result<Value, EvalError> {
+    evaluate(&parse_expr(source)?, vars)
+}
+
+/// Evaluates a parsed expression.
+///
+/// Integers and floats mix freely in arithmetic and comparisons, producing a
+/// float. Strings support `+` and comparisons; bools support `==`, `!=` and
+/// the logical operators, which short-circuit. Any other combination is a
+/// `TypeMismatch`.
+pub fn evaluate(expr: &Expr, vars: &impl VariableStore) -> Result<Value, EvalError> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Variable(name) => vars
+            .get(name)
+            .ok_or_else(|| EvalError::UnknownVariable(name.clone())),
+        Expr::Unary { op, expr } => {
+            let value = evaluate(expr, vars)?;
+            match (op, value) {
+                (UnaryOp::Not, Value::Bool(value)) => Ok(Value::Bool(!value)),
+                (UnaryOp::Neg, Value::Int(value)) => value
+                    .checked_neg()
+                    .map(Value::Int)
+                    .ok_or(EvalError::Overflow),
+                (UnaryOp::Neg, Value::Float(value)) => Ok(Value::Float(-value)),
+                (op, value) => Err(EvalError::TypeMismatch {
+                    op: if *op == UnaryOp::Not { "not" } else { "-" },
+                    left: value.type_name(),
+                    right: None,
+                }),
+            }
+        }
+        Expr::Binary {
+            op: op @ (BinaryOp::And | BinaryOp::Or),
+            left,
+            right,
+        } => {
+            let left = expect_bool(*op, evaluate(left, vars)?)?;
+            if left == (*op == BinaryOp::Or) {
+                return Ok(Value::Bool(left));
+            }
+            Ok(Value::Bool(expect_bool(*op, evaluate(right, vars)?)?))
+        }
+        Expr::Binary { op, left, right } => {
+            binary(*op, evaluate(left, vars)?, evaluate(right, vars)?)
+        }
+    }
+}
+
+impl Choice {
+    /// Returns whether the choice should be shown, evaluating its condition
+    /// against `vars`. A choice without a condition is always available.
+    pub fn is_available(&self, vars: &impl VariableStore) -> Result<bool, EvalError> {
+        let value = match (&self.condition_expr, &self.condition) {
+            (Some(expr), _) => evaluate(expr, vars)?,
+            (None, Some(condition)) => evaluate_condition(condition, vars)?,
+            (None, None) => return Ok(true),
+        };
+        match value {
+            Value::Bool(value) => Ok(value),
+            other => Err(EvalError::ConditionNotBool {
+                found: other.type_name(),
+            }),
+        }
+    }
+}
+
+fn expect_bool(op: BinaryOp, value: Value) -> Result<bool, EvalError> {
+    match value {
+        Value::Bool(value) => Ok(value),
+        other => Err(EvalError::TypeMismatch {
+            op: op.symbol(),
+            left: other.type_name(),
+            right: None,
+        }),
+    }
+}
+
+pub(crate) fn binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, EvalError> {
+    use BinaryOp::*;
+
+    let mismatch = |left: &Value, right: &Value| EvalError::TypeMismatch {
+        op: op.symbol(),
+        left: left.type_name(),
+        right: Some(right.type_name()),
+    };
+
+    match (&left, &right) {
+        (Value::Int(a), Value::Int(b)) => {
+            let (a, b) = (*a, *b);
+            let checked = match op {
+                Add => a.checked_add(b),
+                Sub => a.checked_sub(b),
+                Mul => a.checked_mul(b),
+                Div | Rem if b == 0 => return Err(EvalError::DivisionByZero),
+                Div => a.checked_div(b),
+                Rem => a.checked_rem(b),
+                _ => return Ok(Value::Bool(compare(op, a.cmp(&b)))),
+            };
+            checked.map(Value::Int).ok_or(EvalError::Overflow)
+        }
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            let (a, b) = (as_float(&left), as_float(&right));
+            match op {
+                Add => Ok(Value::Float(a + b)),
+                Sub => Ok(Value::Float(a - b)),
+                Mul => Ok(Value::Float(a * b)),
+                Div | Rem if b == 0.0 => Err(EvalError::DivisionByZero),
+                Div => Ok(Value::Float(a / b)),
+                Rem => Ok(Value::Float(a % b)),
+                _ => match a.partial_cmp(&b) {
+                    Some(ordering) => Ok(Value::Bool(compare(op, ordering))),
+                    None => Ok(Value::Bool(op == Ne)),
+                },
+            }
+        }
+        (Value::Str(a), Value::Str(b)) => match op {
+            Add => Ok(Value::Str(format!("{}{}", a, b))),
+            Eq | Ne | Lt | Le | Gt | Ge => Ok(Value::Bool(compare(op, a.cmp(b)))),
+            _ => Err(mismatch(&left, &right)),
+        },
+        (Value::Bool(a), Value::Bool(b)) => match op {
+            Eq => Ok(Value::Bool(a == b)),
+            Ne => Ok(Value::Bool(a != b)),
+            _ => Err(mismatch(&left, &right)),
+        },
+        _ => Err(mismatch(&left, &right)),
+    }
+}
+
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(value) => *value as f64,
+        Value::Float(value) => *value,
+        _ => unreachable!("as_float is only called on numbers"),
+    }
+}
+
+fn compare(op: BinaryOp, ordering: std::cmp::Ordering) -> bool {
+    match op {
+        BinaryOp::Eq => ordering.is_eq(),
+        BinaryOp::Ne => ordering.is_ne(),
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::Le => ordering.is_le(),
+        BinaryOp::Gt => ordering.is_gt(),
+        BinaryOp::Ge => ordering.is_ge(),
+        _ => unreachable!("compare is only called with comparison operators"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn vars() -> HashMap<String, Value> {
+        HashMap::from([
+            ("reputation".to_string(), Value::Int(2)),
+            ("gold".to_string(), Value::Float(10.5)),
+            ("helped".to_string(), Value::Bool(true)),
+            ("name".to_string(), Value::from("Kim")),
+        ])
+    }
+
+    #[test]
+    fn test_evaluate_condition() {
+        let vars = vars();
+        let eval = |source| evaluate_condition(source, &vars).unwrap();
+        assert_eq!(eval("reputation < 3"), Value::Bool(true));
+        assert_eq!(eval("reputation * 2 + 1"), Value::Int(5));
+        assert_eq!(eval("7 / 2"), Value::Int(3));
+        assert_eq!(eval("gold + reputation"), Value::Float(12.5));
+        assert_eq!(eval("gold > reputation and not helped"), Value::Bool(false));
+        assert_eq!(eval("name + \"!\" == 'Kim!'"), Value::Bool(true));
+        assert_eq!(eval("helped or missing"), Value::Bool(true));
+        assert_eq!(eval("-(reputation - 5) % 2"), Value::Int(1));
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        let vars = vars();
+        let eval = |source| evaluate_condition(source, &vars).unwrap_err();
+        assert_eq!(
+            eval("missing > 1"),
+            EvalError::UnknownVariable("missing".to_string())
+        );
+        assert_eq!(
+            eval("name > 1"),
+            EvalError::TypeMismatch {
+                op: ">",
+                left: "string",
+                right: Some("int"),
+            }
+        );
+        assert_eq!(
+            eval("helped and 1").to_string(),
+            "Cannot apply 'and' to int"
+        );
+        assert_eq!(
+            eval("not reputation").to_string(),
+            "Cannot apply 'not' to int"
+        );
+        assert_eq!(eval("reputation / 0"), EvalError::DivisionByZero);
+        assert_eq!(eval("9223372036854775807 + 1"), EvalError::Overflow);
+        assert!(matches!(eval("a ="), EvalError::Syntax(_)));
+    }
+
+    #[test]
+    fn test_choice_is_available() {
+        let dialogue =
+            parse("::start\n* A => a @if reputation < 3\n* B => b @if gold\n* C => c\n").unwrap();
+        let choices = &dialogue["start"].choices;
+        let vars = vars();
+        assert_eq!(choices[0].is_available(&vars), Ok(true));
+        assert_eq!(
+            choices[1].is_available(&vars),
+            Err(EvalError::ConditionNotBool { found: "float" })
+        );
+        assert_eq!(choices[2].is_available(&vars), Ok(true));
+    }
+}
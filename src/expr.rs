@@ -0,0 +1,763 @@
+use std::error::Error;
+use std::fmt;
+
+/// A literal or computed value in a condition expression.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl Value {
+    /// Returns the name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A prefix operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum UnaryOp {
+    /// `not x` or `!x`.
+    Not,
+    /// `-x`.
+    Neg,
+}
+
+/// An infix operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `and` or `&&`.
+    And,
+    /// `or` or `||`.
+    Or,
+}
+
+impl BinaryOp {
+    /// Returns the operator as written in canonical source.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        }
+    }
+}
+
+/// A parsed condition expression.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Expr {
+    Literal(Value),
+    Variable(String),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// Binding strength, matching the levels listed on `parse_expr`.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op, .. } => match op {
+                BinaryOp::Or => 1,
+                BinaryOp::And => 2,
+                BinaryOp::Eq
+                | BinaryOp::Ne
+                | BinaryOp::Lt
+                | BinaryOp::Le
+                | BinaryOp::Gt
+                | BinaryOp::Ge => 4,
+                BinaryOp::Add | BinaryOp::Sub => 5,
+                BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+            },
+            Expr::Unary {
+                op: UnaryOp::Not, ..
+            } => 3,
+            Expr::Unary {
+                op: UnaryOp::Neg, ..
+            } => 7,
+            Expr::Literal(_) | Expr::Variable(_) => 8,
+        }
+    }
+
+    /// Writes `self`, parenthesized if it binds looser than `min`.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min: u8) -> fmt::Result {
+        if self.precedence() < min {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+/// Writes the expression as canonical source that `parse_expr` reads back
+/// into the same tree, with only the parentheses precedence requires.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(Value::Str(value)) => {
+                f.write_str("\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Expr::Literal(Value::Float(value)) if value.fract() == 0.0 => {
+                write!(f, "{}.0", value)
+            }
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Variable(name) => f.write_str(name),
+            Expr::Unary { op, expr } => {
+                let prec = self.precedence();
+                f.write_str(if *op == UnaryOp::Not { "not " } else { "-" })?;
+                expr.fmt_operand(f, prec)
+            }
+            Expr::Binary { op, left, right } => {
+                let prec = self.precedence();
+                // Comparisons do not chain, so neither side may be another one.
+                let left_min = if prec == 4 { prec + 1 } else { prec };
+                left.fmt_operand(f, left_min)?;
+                write!(f, " {} ", op.symbol())?;
+                right.fmt_operand(f, prec + 1)
+            }
+        }
+    }
+}
+
+/// A syntax error in an expression.
+///
+/// `start` and `end` are byte offsets into the expression source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.start)
+    }
+}
+
+impl Error for ExprError {}
+
+/// Parses an expression such as `reputation < 3 and not helped`.
+///
+/// The grammar, from loosest to tightest binding:
+///
+/// * `or` / `||`
+/// * `and` / `&&`
+/// * `not` / `!`
+/// * `==`, `!=`, `<`, `<=`, `>`, `>=` (not chainable)
+/// * `+`, `-`
+/// * `*`, `/`, `%`
+/// * unary `-`
+/// * literals (`12`, `1.5`, `true`, `"text"` or `'text'`), variables and
+///   parentheses
+pub fn parse_expr(source: &str) -> Result<Expr, ExprError> {
+    let tokens = tokenize(source)?;
+    let mut parser = ExprParser {
+        tokens,
+        pos: 0,
+        source_len: source.len(),
+    };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(token.error(format!("Unexpected {}", token.kind.describe()))),
+    }
+}
+
+/// Parses a comma-separated list of expressions, such as call arguments.
+/// An empty or all-whitespace source is an empty list.
+pub(crate) fn parse_expr_list(source: &str) -> Result<Vec<Expr>, ExprError> {
+    let tokens = tokenize(source)?;
+    let mut parser = ExprParser {
+        tokens,
+        pos: 0,
+        source_len: source.len(),
+    };
+    let mut exprs = Vec::new();
+    if parser.peek().is_none() {
+        return Ok(exprs);
+    }
+    loop {
+        exprs.push(parser.parse_or()?);
+        match parser.peek() {
+            None => return Ok(exprs),
+            Some(Token {
+                kind: TokenKind::Comma,
+                ..
+            }) => parser.pos += 1,
+            Some(token) => return Err(token.error(format!("Unexpected {}", token.kind.describe()))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl TokenKind {
+    fn describe(&self) -> String {
+        match self {
+            TokenKind::Int(value) => format!("number '{}'", value),
+            TokenKind::Float(value) => format!("number '{}'", value),
+            TokenKind::Str(_) => "string".to_string(),
+            TokenKind::Ident(name) => format!("'{}'", name),
+            TokenKind::Op(op) => format!("'{}'", op),
+            TokenKind::LParen => "'('".to_string(),
+            TokenKind::RParen => "')'".to_string(),
+            TokenKind::Comma => "','".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> ExprError {
+        ExprError {
+            message,
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+/// Operators, longest first so that `<=` wins over `<`.
+const OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let kind = if c.is_ascii_digit() {
+            let mut end = start;
+            let mut is_float = false;
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_ascii_digit() || (c == '.' && !is_float) {
+                    is_float |= c == '.';
+                    end = i + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let text = &source[start..end];
+            let invalid = || ExprError {
+                message: format!("Invalid number '{}'", text),
+                start,
+                end,
+            };
+            if is_float {
+                TokenKind::Float(text.parse().map_err(|_| invalid())?)
+            } else {
+                TokenKind::Int(text.parse().map_err(|_| invalid())?)
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    end = i + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            TokenKind::Ident(source[start..end].to_string())
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut value = String::new();
+            let mut closed = false;
+            while let Some((_, next)) = chars.next() {
+                match next {
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, 't')) => value.push('\t'),
+                        Some((_, escaped)) => value.push(escaped),
+                        None => break,
+                    },
+                    _ if next == c => {
+                        closed = true;
+                        break;
+                    }
+                    _ => value.push(next),
+                }
+            }
+            if !closed {
+                return Err(ExprError {
+                    message: "Unterminated string".to_string(),
+                    start,
+                    end: source.len(),
+                });
+            }
+            TokenKind::Str(value)
+        } else if c == '(' {
+            chars.next();
+            TokenKind::LParen
+        } else if c == ')' {
+            chars.next();
+            TokenKind::RParen
+        } else if c == ',' {
+            chars.next();
+            TokenKind::Comma
+        } else if let Some(op) = OPERATORS.iter().find(|op| source[start..].starts_with(*op)) {
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            TokenKind::Op(op)
+        } else {
+            let end = start + c.len_utf8();
+            let message = if c == '=' {
+                "Unexpected '='; use '==' for comparison".to_string()
+            } else {
+                format!("Unexpected character '{}'", c)
+            };
+            return Err(ExprError {
+                message,
+                start,
+                end,
+            });
+        };
+        let end = chars.peek().map_or(source.len(), |&(i, _)| i);
+        tokens.push(Token { kind, start, end });
+    }
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    source_len: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Consumes the next token if it is one of the given operators or
+    /// keywords, returning the matching entry.
+    fn eat(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        let token = self.peek()?;
+        let found = ops.iter().copied().find(|op| match &token.kind {
+            TokenKind::Op(symbol) => symbol == op,
+            TokenKind::Ident(name) => name == op,
+            _ => false,
+        })?;
+        self.pos += 1;
+        Some(found)
+    }
+
+    fn end_error(&self, message: &str) -> ExprError {
+        ExprError {
+            message: message.to_string(),
+            start: self.source_len,
+            end: self.source_len,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.parse_and()?;
+        while self.eat(&["or", "||"]).is_some() {
+            let right = self.parse_and()?;
+            left = binary(BinaryOp::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.parse_not()?;
+        while self.eat(&["and", "&&"]).is_some() {
+            let right = self.parse_not()?;
+            left = binary(BinaryOp::And, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ExprError> {
+        if self.eat(&["not", "!"]).is_some() {
+            let expr = self.parse_not()?;
+            return Ok(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ExprError> {
+        const COMPARISONS: &[&str] = &["==", "!=", "<=", ">=", "<", ">"];
+        let left = self.parse_additive()?;
+        let Some(symbol) = self.eat(COMPARISONS) else {
+            return Ok(left);
+        };
+        let right = self.parse_additive()?;
+        if let Some(token) = self.peek() {
+            if matches!(&token.kind, TokenKind::Op(op) if COMPARISONS.contains(op)) {
+                return Err(token.error("Comparison operators cannot be chained".to_string()));
+            }
+        }
+        let op = match symbol {
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<=" => BinaryOp::Le,
+            ">=" => BinaryOp::Ge,
+            "<" => BinaryOp::Lt,
+            _ => BinaryOp::Gt,
+        };
+        Ok(binary(op, left, right))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.parse_multiplicative()?;
+        while let Some(symbol) = self.eat(&["+", "-"]) {
+            let op = if symbol == "+" {
+                BinaryOp::Add
+            } else {
+                BinaryOp::Sub
+            };
+            let right = self.parse_multiplicative()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.parse_unary()?;
+        while let Some(symbol) = self.eat(&["*", "/", "%"]) {
+            let op = match symbol {
+                "*" => BinaryOp::Mul,
+                "/" => BinaryOp::Div,
+                _ => BinaryOp::Rem,
+            };
+            let right = self.parse_unary()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExprError> {
+        if self.eat(&["-"]).is_some() {
+            let expr = self.parse_unary()?;
+            return Ok(Expr::Unary {
+                op: UnaryOp::Neg,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ExprError> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(self.end_error("Expected an expression"));
+        };
+        self.pos += 1;
+        match &token.kind {
+            TokenKind::Int(value) => Ok(Expr::Literal(Value::Int(*value))),
+            TokenKind::Float(value) => Ok(Expr::Literal(Value::Float(*value))),
+            TokenKind::Str(value) => Ok(Expr::Literal(Value::Str(value.clone()))),
+            TokenKind::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "and" | "or" | "not" => {
+                    Err(token.error(format!("Expected an expression, found '{}'", name)))
+                }
+                _ => Ok(Expr::Variable(name.clone())),
+            },
+            TokenKind::LParen => {
+                let expr = self.parse_or()?;
+                match self.peek() {
+                    Some(Token {
+                        kind: TokenKind::RParen,
+                        ..
+                    }) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    Some(token) => {
+                        Err(token.error(format!("Expected ')', found {}", token.kind.describe())))
+                    }
+                    None => Err(token.error("Unclosed '('".to_string())),
+                }
+            }
+            kind => Err(token.error(format!("Expected an expression, found {}", kind.describe()))),
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    fn int(value: i64) -> Box<Expr> {
+        Box::new(Expr::Literal(Value::Int(value)))
+    }
+
+    #[test]
+    fn test_parse_comparison() {
+        assert_eq!(
+            parse_expr("reputation < 3").unwrap(),
+            Expr::Binary {
+                op: BinaryOp::Lt,
+                left: var("reputation"),
+                right: int(3),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let expr = parse_expr("not a or b and gold + 2 * 3 >= -1").unwrap();
+        let expected = Expr::Binary {
+            op: BinaryOp::Or,
+            left: Box::new(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: var("a"),
+            }),
+            right: Box::new(Expr::Binary {
+                op: BinaryOp::And,
+                left: var("b"),
+                right: Box::new(Expr::Binary {
+                    op: BinaryOp::Ge,
+                    left: Box::new(Expr::Binary {
+                        op: BinaryOp::Add,
+                        left: var("gold"),
+                        right: Box::new(Expr::Binary {
+                            op: BinaryOp::Mul,
+                            left: int(2),
+                            right: int(3),
+                        }),
+                    }),
+                    right: Box::new(Expr::Unary {
+                        op: UnaryOp::Neg,
+                        expr: int(1),
+                    }),
+                }),
+            }),
+        };
+        assert_eq!(expr, expected);
+        assert_eq!(
+            parse_expr("!a && b || c").unwrap(),
+            parse_expr("not a and b or c").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_literals() {
+        assert_eq!(parse_expr("1.5").unwrap(), Expr::Literal(Value::Float(1.5)));
+        assert_eq!(
+            parse_expr("true").unwrap(),
+            Expr::Literal(Value::Bool(true))
+        );
+        assert_eq!(
+            parse_expr(r#"name == "Kim \"K\"""#).unwrap(),
+            Expr::Binary {
+                op: BinaryOp::Eq,
+                left: var("name"),
+                right: Box::new(Expr::Literal(Value::Str("Kim \"K\"".to_string()))),
+            }
+        );
+        assert_eq!(
+            parse_expr("'a'").unwrap(),
+            Expr::Literal(Value::Str("a".to_string()))
+        );
+        assert_eq!(
+            parse_expr("(호감도)").unwrap(),
+            Expr::Variable("호감도".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse_expr("gold = 3").unwrap_err();
+        assert_eq!(err.message, "Unexpected '='; use '==' for comparison");
+        assert_eq!((err.start, err.end), (5, 6));
+
+        assert_eq!(
+            parse_expr("").unwrap_err().message,
+            "Expected an expression"
+        );
+        assert_eq!(parse_expr("a <").unwrap_err().start, 3);
+        assert_eq!(parse_expr("(a").unwrap_err().message, "Unclosed '('");
+        assert_eq!(parse_expr("a b").unwrap_err().message, "Unexpected 'b'");
+        assert_eq!(
+            parse_expr("1 < a < 3").unwrap_err().message,
+            "Comparison operators cannot be chained"
+        );
+        assert_eq!(
+            parse_expr("'open").unwrap_err().message,
+            "Unterminated string"
+        );
+        assert_eq!(parse_expr("a and").unwrap_err().start, 5);
+        assert_eq!(parse_expr("a, b").unwrap_err().message, "Unexpected ','");
+    }
+
+    #[test]
+    fn test_parse_expr_list() {
+        assert_eq!(parse_expr_list("  ").unwrap(), vec![]);
+        assert_eq!(
+            parse_expr_list("a, (1, 2").unwrap_err().message,
+            "Expected ')', found ','"
+        );
+        assert_eq!(
+            parse_expr_list("gold * 2, 'x, y'").unwrap(),
+            vec![
+                Expr::Binary {
+                    op: BinaryOp::Mul,
+                    left: var("gold"),
+                    right: int(2),
+                },
+                Expr::Literal(Value::Str("x, y".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for source in [
+            "reputation < 3",
+            "not a or b and gold + 2 * 3 >= -1",
+            "(a or b) and not (c == d)",
+            "a - (b - c) * -(d % 2)",
+            "(1 < 2) == true",
+            "name == \"Kim \\\"K\\\"\\n\" + 'x'",
+            "1.0 + 2.5",
+        ] {
+            let expr = parse_expr(source).unwrap();
+            let printed = expr.to_string();
+            assert_eq!(parse_expr(&printed).unwrap(), expr, "{}", printed);
+        }
+        assert_eq!(
+            parse_expr("((a)) + (b * c)").unwrap().to_string(),
+            "a + b * c"
+        );
+        assert_eq!(
+            parse_expr("!a && (b || c)").unwrap().to_string(),
+            "not a and (b or c)"
+        );
+        assert_eq!(parse_expr("2.0").unwrap().to_string(), "2.0");
+    }
+}
@@ -0,0 +1,303 @@
+//! Source formatting that keeps comments.
+//!
+//! Unlike printing a parsed `Dialogue`, which loses `//` comments and
+//! layout, `format` works on the lossless tree from `parse_cst`.
+
+use crate::{parse_cst, LineKind, ParseError};
+
+/// Where a line goes within a formatted node. Lines are emitted group by
+/// group, keeping their relative order within each group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Group {
+    Meta,
+    Action,
+    Tags,
+    Body,
+    Choice,
+    Next,
+}
+
+/// A line of a node together with the comments (and `@if` line) above it,
+/// which move with it.
+struct Item {
+    group: Group,
+    lines: Vec<String>,
+    /// Whether a blank line separated this item from the previous one, which
+    /// is in the same group.
+    blank_before: bool,
+}
+
+struct NodeBlock {
+    /// Comments above the `::name` line.
+    leading: Vec<String>,
+    header: String,
+    items: Vec<Item>,
+}
+
+/// Comments and blank lines waiting for the line they belong to.
+#[derive(Default)]
+struct Pending {
+    lines: Vec<String>,
+    blank_before: bool,
+    /// A blank line was seen after `lines` started.
+    blank_inside: bool,
+}
+
+impl Pending {
+    fn blank(&mut self) {
+        if self.lines.is_empty() {
+            self.blank_before = true;
+        } else {
+            self.blank_inside = true;
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        if std::mem::take(&mut self.blank_inside) {
+            self.lines.push(String::new());
+        }
+        self.lines.push(line);
+    }
+
+    fn take(&mut self) -> Pending {
+        std::mem::take(self)
+    }
+}
+
+/// Reformats a Varion script, keeping `//` comments and blank lines.
+///
+/// Within each node, lines are reordered into meta entries, actions, tags,
+/// body, choices and `@next:`, each group keeping its original order. A
+/// comment moves with the line below it, and an `@if` line with its choice.
+/// Spacing around `::`, `@key:`, `#`, `=>` and `@if` is normalized, runs of
+/// blank lines are collapsed, and nodes are separated by exactly one blank
+/// line. Body lines are kept verbatim.
+///
+/// The script must parse; otherwise its first error is returned. The result
+/// always parses to the same `Dialogue` as the input.
+pub fn format(source: &str) -> Result<String, ParseError> {
+    let cst = parse_cst(source);
+    cst.to_dialogue().into_result()?;
+
+    let mut preamble: Vec<String> = Vec::new();
+    let mut nodes: Vec<NodeBlock> = Vec::new();
+    let mut pending = Pending::default();
+
+    for line in cst.lines() {
+        let (group, formatted) = match &line.kind {
+            LineKind::Blank => {
+                pending.blank();
+                continue;
+            }
+            LineKind::Comment => {
+                pending.push(line.slice(line.content).to_string());
+                continue;
+            }
+            LineKind::Header { name } => {
+                let leading = pending.take();
+                nodes.push(NodeBlock {
+                    leading: leading.lines,
+                    header: format!("::{}", line.slice(*name)),
+                    items: Vec::new(),
+                });
+                continue;
+            }
+            LineKind::If { condition } => {
+                pending.push(format!("@if {}", line.slice(*condition)));
+                continue;
+            }
+            LineKind::Directive { key, value } => {
+                let key = line.slice(*key);
+                let group = match key {
+                    "action" => Group::Action,
+                    "next" => Group::Next,
+                    _ => Group::Meta,
+                };
+                let value = value.map_or("", |value| line.slice(value));
+                (group, format!("@{}: {}", key, value).trim_end().to_string())
+            }
+            LineKind::Tags { tags } => {
+                let tags: Vec<String> = tags
+                    .iter()
+                    .map(|tag| {
+                        let tag = line.slice(*tag);
+                        format!("#{}", tag.strip_prefix('#').unwrap_or(tag))
+                    })
+                    .collect();
+                (Group::Tags, tags.join(" "))
+            }
+            LineKind::Choice {
+                text,
+                target,
+                condition,
+            } => {
+                let mut choice = format!("* {}", line.slice(*text));
+                if let Some(target) = target {
+                    choice = format!("{} => {}", choice, line.slice(*target));
+                }
+                if let Some(condition) = condition {
+                    choice = format!("{} @if {}", choice, line.slice(*condition));
+                }
+                (Group::Choice, choice)
+            }
+            LineKind::Include { path } => {
+                let path = path.map_or("", |path| line.slice(path));
+                (Group::Meta, format!("@include \"{}\"", path))
+            }
+            LineKind::Namespace { name } => {
+                let name = name.map_or("", |name| line.slice(name));
+                (Group::Meta, format!("@namespace {}", name))
+            }
+            LineKind::Body => (Group::Body, line.text.clone()),
+        };
+
+        let mut item = pending.take();
+        item.push(formatted);
+        let Some(node) = nodes.last_mut() else {
+            // Only `@start:`, `@include` and `@namespace` may appear before
+            // the first node.
+            if item.blank_before && !preamble.is_empty() {
+                preamble.push(String::new());
+            }
+            preamble.extend(item.lines);
+            continue;
+        };
+        // Blank lines only survive between neighbouring lines of one group.
+        let same_group = node.items.last().map(|last| last.group) == Some(group);
+        node.items.push(Item {
+            group,
+            lines: item.lines,
+            blank_before: item.blank_before && same_group,
+        });
+    }
+
+    let mut out: Vec<String> = preamble;
+    for mut node in nodes {
+        if !out.is_empty() {
+            out.push(String::new());
+        }
+        out.append(&mut node.leading);
+        out.push(node.header);
+        node.items.sort_by_key(|item| item.group);
+        for mut item in node.items {
+            if item.blank_before {
+                out.push(String::new());
+            }
+            out.append(&mut item.lines);
+        }
+    }
+    if !pending.lines.is_empty() {
+        if pending.blank_before && !out.is_empty() {
+            out.push(String::new());
+        }
+        out.append(&mut pending.lines);
+    }
+
+    let mut formatted = out.join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use std::fs;
+
+    #[test]
+    fn test_format_normalizes_and_keeps_comments() {
+        let script = "\
+// Guard encounter
+@start:intro
+
+
+// The first node
+::  intro
+  Hello.
+// greets the player
+@action:set gold = 1
+#greeting   #npc
+@who:Guard
+*Pay=>pass@if gold > 0
+// run away
+@if   not caught
+*   Run =>flee
+
+
+
+@mood :  angry
+// trailing note
+::pass
+@next:intro
+// end of file
+";
+        let expected = "\
+// Guard encounter
+@start: intro
+
+// The first node
+::intro
+@who: Guard
+@mood: angry
+// greets the player
+@action: set gold = 1
+#greeting #npc
+  Hello.
+* Pay => pass @if gold > 0
+// run away
+@if not caught
+* Run => flee
+
+// trailing note
+::pass
+@next: intro
+// end of file
+";
+        let formatted = format(script).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert_eq!(parse(&formatted).unwrap(), parse(script).unwrap());
+    }
+
+    #[test]
+    fn test_format_keeps_blank_lines_within_groups() {
+        let script = "::start\nFirst.\n\n\nSecond.\n* A => start\n\n// B\n* B => start\n";
+        assert_eq!(
+            format(script).unwrap(),
+            "::start\nFirst.\n\nSecond.\n* A => start\n\n// B\n* B => start\n"
+        );
+    }
+
+    #[test]
+    fn test_format_includes() {
+        let script = "@include   \"scenes/a.va\"\n@start:  a\n::a\n";
+        assert_eq!(
+            format(script).unwrap(),
+            "@include \"scenes/a.va\"\n@start: a\n\n::a\n"
+        );
+    }
+
+    #[test]
+    fn test_format_examples_preserve_meaning() {
+        for path in [
+            "examples/varion_examples.va",
+            "examples/varion_long_example.vion",
+        ] {
+            let script = fs::read_to_string(path).unwrap();
+            let formatted = format(&script).unwrap();
+            assert_eq!(parse(&formatted).unwrap(), parse(&script).unwrap());
+            assert_eq!(format(&formatted).unwrap(), formatted);
+        }
+    }
+
+    #[test]
+    fn test_format_rejects_invalid_scripts() {
+        assert!(matches!(
+            format("::start\n* broken choice\n"),
+            Err(ParseError::InvalidChoiceFormat { .. })
+        ));
+        assert_eq!(format("").unwrap(), "");
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{Dialogue, Node};
+
+/// Fill colours for tagged nodes, assigned to tags in order of first use.
+const PALETTE: [&str; 8] = [
+    "#a6cee3", "#b2df8a", "#fdbf6f", "#cab2d6", "#fb9a99", "#ffff99", "#8dd3c7", "#d9d9d9",
+];
+
+/// The colour of a missing node and the edges that point to it.
+const UNRESOLVED: &str = "#e31a1c";
+
+/// An edge of the dialogue graph.
+struct Edge<'a> {
+    from: &'a str,
+    to: &'a str,
+    /// The choice text and condition; `None` for `@next:`.
+    label: Option<String>,
+}
+
+/// The nodes and edges shared by every output format.
+struct Graph<'a> {
+    nodes: Vec<&'a Node>,
+    /// Targets that no node declares, in order of first use.
+    unresolved: Vec<&'a str>,
+    edges: Vec<Edge<'a>>,
+    /// Tags in order of first use; a node is coloured by its first tag.
+    tags: Vec<&'a str>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(dialogue: &'a Dialogue) -> Self {
+        let mut graph = Graph {
+            nodes: dialogue.iter().collect(),
+            unresolved: Vec::new(),
+            edges: Vec::new(),
+            tags: Vec::new(),
+        };
+        for node in dialogue.iter() {
+            if let Some(tag) = node.tags.first() {
+                if !graph.tags.contains(&tag.as_str()) {
+                    graph.tags.push(tag);
+                }
+            }
+            for choice in &node.choices {
+                let label = match &choice.condition {
+                    Some(condition) => format!("{}\n[{}]", choice.text, condition),
+                    None => choice.text.clone(),
+                };
+                graph.edge(dialogue, node, &choice.target_node, Some(label));
+            }
+            if let Some(next) = &node.next {
+                graph.edge(dialogue, node, next, None);
+            }
+        }
+        graph
+    }
+
+    /// Adds an edge from `from` to the node `target` resolves to, or to
+    /// `target` as written if it does not resolve.
+    fn edge(
+        &mut self,
+        dialogue: &'a Dialogue,
+        from: &'a Node,
+        target: &'a str,
+        label: Option<String>,
+    ) {
+        let to = match dialogue.resolve_from(from, target) {
+            Some(node) => node.name.as_str(),
+            None => {
+                if !self.unresolved.contains(&target) {
+                    self.unresolved.push(target);
+                }
+                target
+            }
+        };
+        self.edges.push(Edge {
+            from: &from.name,
+            to,
+            label,
+        });
+    }
+
+    fn is_unresolved(&self, name: &str) -> bool {
+        self.unresolved.contains(&name)
+    }
+
+    /// The index of the tag `node` is coloured by.
+    fn tag_index(&self, node: &Node) -> Option<usize> {
+        let tag = node.tags.first()?;
+        self.tags.iter().position(|t| t == tag)
+    }
+}
+
+impl Dialogue {
+    /// Draws the dialogue as a Graphviz DOT digraph.
+    ///
+    /// Nodes appear in declaration order, filled by their first tag. Each
+    /// choice is an edge labelled with its text and condition, and `@next:`
+    /// is a dashed edge. Targets that no node declares are drawn in red.
+    pub fn to_dot(&self) -> String {
+        let graph = Graph::new(self);
+        let mut out = String::from("digraph dialogue {\n");
+        for node in &graph.nodes {
+            match graph.tag_index(node) {
+                Some(index) => out.push_str(&format!(
+                    "    {} [style=filled, fillcolor={}];\n",
+                    dot_string(&node.name),
+                    dot_string(PALETTE[index % PALETTE.len()])
+                )),
+                None => out.push_str(&format!("    {};\n", dot_string(&node.name))),
+            }
+        }
+        for name in &graph.unresolved {
+            out.push_str(&format!(
+                "    {} [shape=box, style=dashed, color={c}, fontcolor={c}];\n",
+                dot_string(name),
+                c = dot_string(UNRESOLVED)
+            ));
+        }
+        for edge in &graph.edges {
+            let mut attributes = vec![match &edge.label {
+                Some(label) => format!("label={}", dot_string(label)),
+                None => "style=dashed".to_string(),
+            }];
+            if graph.is_unresolved(edge.to) {
+                attributes.push(format!("color={}", dot_string(UNRESOLVED)));
+            }
+            out.push_str(&format!(
+                "    {} -> {} [{}];\n",
+                dot_string(edge.from),
+                dot_string(edge.to),
+                attributes.join(", ")
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Draws the dialogue as a Mermaid flowchart, styled like `to_dot`.
+    ///
+    /// Node names are not valid Mermaid ids in general, so nodes get the ids
+    /// `n0`, `n1`, ... and are labelled with their names.
+    pub fn to_mermaid(&self) -> String {
+        let graph = Graph::new(self);
+        let names: Vec<&str> = graph
+            .nodes
+            .iter()
+            .map(|node| node.name.as_str())
+            .chain(graph.unresolved.iter().copied())
+            .collect();
+        let id = |name: &str| format!("n{}", names.iter().position(|n| *n == name).unwrap());
+
+        let mut out = String::from("flowchart TD\n");
+        for name in &names {
+            out.push_str(&format!("    {}[{}]\n", id(name), mermaid_string(name)));
+        }
+        for edge in &graph.edges {
+            let arrow = match &edge.label {
+                Some(label) => format!("-->|{}|", mermaid_string(label)),
+                None => "-.->".to_string(),
+            };
+            out.push_str(&format!(
+                "    {} {} {}\n",
+                id(edge.from),
+                arrow,
+                id(edge.to)
+            ));
+        }
+
+        for index in 0..graph.tags.len() {
+            out.push_str(&format!(
+                "    classDef tag{} fill:{}\n",
+                index,
+                PALETTE[index % PALETTE.len()]
+            ));
+        }
+        for node in &graph.nodes {
+            if let Some(index) = graph.tag_index(node) {
+                out.push_str(&format!("    class {} tag{}\n", id(&node.name), index));
+            }
+        }
+        if !graph.unresolved.is_empty() {
+            out.push_str(&format!(
+                "    classDef unresolved stroke:{c},color:{c},stroke-dasharray:4\n",
+                c = UNRESOLVED
+            ));
+            let ids: Vec<String> = graph.unresolved.iter().map(|name| id(name)).collect();
+            out.push_str(&format!("    class {} unresolved\n", ids.join(",")));
+        }
+        for (index, edge) in graph.edges.iter().enumerate() {
+            if graph.is_unresolved(edge.to) {
+                out.push_str(&format!("    linkStyle {} stroke:{}\n", index, UNRESOLVED));
+            }
+        }
+        out
+    }
+}
+
+/// Quotes `text` as a DOT string. Line breaks become centred `\n` breaks.
+fn dot_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// Quotes `text` as a Mermaid label, using entity codes for characters that
+/// Mermaid would otherwise read as syntax.
+fn mermaid_string(text: &str) -> String {
+    let escaped = text
+        .replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('|', "#124;")
+        .replace('\n', "<br>");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+
+    const SCRIPT: &str = "\
+::start
+#hub
+* Say \"hi\" => greet
+* Fight => boss @if strength >= 3
+
+::greet
+#hub #friendly
+@next: end
+
+::end
+#ending
+* Again => lost
+";
+
+    #[test]
+    fn test_to_dot() {
+        assert_eq!(
+            parse(SCRIPT).unwrap().to_dot(),
+            "\
+digraph dialogue {
+    \"start\" [style=filled, fillcolor=\"#a6cee3\"];
+    \"greet\" [style=filled, fillcolor=\"#a6cee3\"];
+    \"end\" [style=filled, fillcolor=\"#b2df8a\"];
+    \"boss\" [shape=box, style=dashed, color=\"#e31a1c\", fontcolor=\"#e31a1c\"];
+    \"lost\" [shape=box, style=dashed, color=\"#e31a1c\", fontcolor=\"#e31a1c\"];
+    \"start\" -> \"greet\" [label=\"Say \\\"hi\\\"\"];
+    \"start\" -> \"boss\" [label=\"Fight\\n[strength >= 3]\", color=\"#e31a1c\"];
+    \"greet\" -> \"end\" [style=dashed];
+    \"end\" -> \"lost\" [label=\"Again\", color=\"#e31a1c\"];
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        assert_eq!(
+            parse(SCRIPT).unwrap().to_mermaid(),
+            "\
+flowchart TD
+    n0[\"start\"]
+    n1[\"greet\"]
+    n2[\"end\"]
+    n3[\"boss\"]
+    n4[\"lost\"]
+    n0 -->|\"Say #quot;hi#quot;\"| n1
+    n0 -->|\"Fight<br>[strength #gt;= 3]\"| n3
+    n1 -.-> n2
+    n2 -->|\"Again\"| n4
+    classDef tag0 fill:#a6cee3
+    classDef tag1 fill:#b2df8a
+    class n0 tag0
+    class n1 tag0
+    class n2 tag1
+    classDef unresolved stroke:#e31a1c,color:#e31a1c,stroke-dasharray:4
+    class n3,n4 unresolved
+    linkStyle 1 stroke:#e31a1c
+    linkStyle 3 stroke:#e31a1c
+"
+        );
+    }
+}
@@ -1,208 +1,329 @@
 use std::collections::HashMap;
+use std::ops::Index;
+
+mod action;
+mod cst;
+mod error;
+mod eval;
+mod expr;
+pub mod fmt;
+mod graph;
+mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+mod namespace;
+mod parser;
+mod print;
+mod project;
+mod rename;
+mod runner;
+mod state;
+mod text;
+mod validate;
+
+pub use action::{parse_action, ActionKind};
+pub use cst::{parse_cst, Cst, CstLine, CstNode, LineKind};
+pub use error::{ParseError, Span};
+pub use eval::{evaluate, evaluate_condition, EvalError, VariableStore};
+pub use expr::{parse_expr, BinaryOp, Expr, ExprError, UnaryOp, Value};
+pub use lint::{Lint, LintOptions};
+pub use parser::{
+    parse, parse_with_diagnostics, parse_with_options, DuplicateNodePolicy, ParseOptions,
+    ParseOutput,
+};
+pub use project::{
+    parse_project, parse_project_with_loader, FileLoader, Include, ProjectError, ProjectOutput,
+    SourceLoader,
+};
+pub use rename::{apply_edits, rename_node, rename_node_in_files, RenameError, TextEdit};
+pub use runner::{AvailableChoice, DialogueRunner, RunError, Step};
+pub use state::{DialogueState, StateError, STATE_VERSION};
+pub use text::{parse_text, render_text, AlternativeKind, RenderContext, RenderOptions, Segment};
+pub use validate::ValidationError;
 
 /// Represents a single choice in the dialogue.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Equality ignores source locations.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Choice {
     pub text: String,
+    /// The parsed form of `text`.
+    pub text_segments: Vec<Segment>,
     pub target_node: String,
     pub condition: Option<String>,
+    /// The parsed form of `condition`.
+    pub condition_expr: Option<Expr>,
+    /// The whole `* text => target` line.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+    /// The target node name after `=>`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub target_span: Span,
+    /// The condition text, either inline or on the preceding `@if` line.
+    pub condition_span: Option<Span>,
+}
+
+impl PartialEq for Choice {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+            && self.text_segments == other.text_segments
+            && self.target_node == other.target_node
+            && self.condition == other.condition
+            && self.condition_expr == other.condition_expr
+    }
 }
 
 /// Represents an action to be executed.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Equality ignores source locations.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Action {
     pub command: String,
+    /// The parsed form of `command`.
+    pub kind: ActionKind,
+    /// The whole `@action:` line.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+}
+
+impl PartialEq for Action {
+    fn eq(&self, other: &Self) -> bool {
+        self.command == other.command && self.kind == other.kind
+    }
+}
+
+/// Source locations of the parts of a node.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct NodeSpans {
+    /// The whole `::name` line.
+    pub header: Span,
+    /// The node name after `::`.
+    pub name: Span,
+    /// The whole `@key: value` line of each meta entry.
+    pub meta: HashMap<String, Span>,
+    /// The target node name after `@next:`.
+    pub next: Option<Span>,
+    /// Each `#tag`, in the same order as `Node::tags`.
+    pub tags: Vec<Span>,
+    /// From the start of the first body line to the end of the last one.
+    pub body: Option<Span>,
 }
 
 /// Represents a single dialogue node.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Equality ignores source locations, including `file`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Node {
+    /// The full name, including the `namespace.` prefix if there is one.
     pub name: String,
+    /// The `@namespace` of the script the node was declared in.
+    pub namespace: Option<String>,
     pub meta: HashMap<String, String>,
     pub next: Option<String>,
     pub actions: Vec<Action>,
     pub tags: Vec<String>,
     pub body: String,
+    /// The parsed form of `body`, with a `"\n"` literal between lines.
+    pub body_segments: Vec<Segment>,
     pub choices: Vec<Choice>,
+    /// The file the node was parsed from, if one was given.
+    pub file: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub spans: NodeSpans,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.namespace == other.namespace
+            && self.meta == other.meta
+            && self.next == other.next
+            && self.actions == other.actions
+            && self.tags == other.tags
+            && self.body == other.body
+            && self.body_segments == other.body_segments
+            && self.choices == other.choices
+    }
 }
 
 /// Represents the entire parsed dialogue.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Equality ignores source locations.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "DialogueData"))]
 pub struct Dialogue {
-    pub nodes: HashMap<String, Node>,
+    /// Nodes by name, read with `get` and changed with `insert_node` and
+    /// `remove` so that `order` stays in step.
+    nodes: HashMap<String, Node>,
+    /// Node names in declaration order, appended to by `insert_node`.
+    order: Vec<String>,
+    /// The node named by an `@start:` directive, if the script has one.
+    pub start: Option<String>,
+    /// The target node name after `@start:`.
+    pub start_span: Option<Span>,
+    /// The `@namespace` of the script `start` was written in.
+    pub start_namespace: Option<String>,
 }
 
 impl Dialogue {
     pub fn new() -> Self {
         Dialogue {
             nodes: HashMap::new(),
+            order: Vec::new(),
+            start: None,
+            start_span: None,
+            start_namespace: None,
+        }
+    }
+
+    /// Adds a node, returning the node it replaced, if any.
+    ///
+    /// A replaced node keeps its original position in `order`.
+    pub fn insert_node(&mut self, node: Node) -> Option<Node> {
+        if !self.nodes.contains_key(&node.name) {
+            self.order.push(node.name.clone());
+        }
+        self.nodes.insert(node.name.clone(), node)
+    }
+
+    /// Removes a node, returning it if it was present.
+    pub fn remove(&mut self, name: &str) -> Option<Node> {
+        let node = self.nodes.remove(name)?;
+        self.order.retain(|n| n != name);
+        Some(node)
+    }
+
+    /// Returns the node with the given full name.
+    pub fn get(&self, name: &str) -> Option<&Node> {
+        self.nodes.get(name)
+    }
+
+    /// Returns the node with the given full name for editing. Its `name`
+    /// must not be changed; `remove` it and `insert_node` it again instead.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.nodes.get_mut(name)
+    }
+
+    /// Returns `true` if a node with the given full name exists.
+    pub fn contains(&self, name: &str) -> bool {
+        self.nodes.contains_key(name)
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns `true` if there are no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns the node names in declaration order.
+    pub fn order(&self) -> &[String] {
+        &self.order
+    }
+
+    /// Iterates over the nodes in declaration order.
+    pub fn iter(&self) -> impl Iterator<Item = &Node> {
+        self.order.iter().filter_map(|name| self.nodes.get(name))
+    }
+
+    /// Returns the node the dialogue starts at: the one named by `@start:`
+    /// if present, otherwise the first declared node.
+    pub fn entry_node(&self) -> Option<&Node> {
+        match &self.start {
+            Some(start) => self.resolve(self.start_namespace.as_deref(), start),
+            None => self.iter().next(),
         }
     }
 }
 
-impl Default for Dialogue {
-    fn default() -> Self {
-        Self::new()
+impl PartialEq for Dialogue {
+    fn eq(&self, other: &Self) -> bool {
+        self.nodes == other.nodes
+            && self.order == other.order
+            && self.start == other.start
+            && self.start_namespace == other.start_namespace
     }
 }
 
-/// Parses a Varion script string into a Dialogue struct.
-///
-/// # Arguments
+/// Returns the node with the given full name.
 ///
-/// * `script` - A string slice that holds the Varion script.
+/// # Panics
 ///
-/// # Returns
-///
-/// * `Ok(Dialogue)` if parsing is successful.
-/// * `Err(String)` with an error message if parsing fails.
-pub fn parse(script: &str) -> Result<Dialogue, String> {
-    let mut dialogue = Dialogue::new();
-    let mut current_node: Option<Node> = None;
-    let mut pending_condition: Option<String> = None;
-
-    for (line_num, line) in script.lines().enumerate() {
-        let trimmed_line = line.trim();
-        let line_err = |msg: &str| format!("Error on line {}: {}", line_num + 1, msg);
-
-        if trimmed_line.is_empty() || trimmed_line.starts_with("//") {
-            continue;
-        }
+/// Panics if there is no such node.
+impl Index<&str> for Dialogue {
+    type Output = Node;
 
-        if trimmed_line.starts_with("::") {
-            if let Some(node) = current_node.take() {
-                dialogue.nodes.insert(node.name.clone(), node);
-            }
-            if pending_condition.is_some() {
-                return Err(line_err("Dangling @if condition before new node."));
-            }
+    fn index(&self, name: &str) -> &Node {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no node named '{}'", name))
+    }
+}
 
-            let node_name = trimmed_line[2..].trim().to_string();
-            if node_name.is_empty() {
-                return Err(line_err(
-                    "Node declaration '::' must be followed by a name.",
-                ));
-            }
-            current_node = Some(Node {
-                name: node_name,
-                meta: HashMap::new(),
-                next: None,
-                actions: Vec::new(),
-                tags: Vec::new(),
-                body: String::new(),
-                choices: Vec::new(),
-            });
-            pending_condition = None; // Reset for new node
-        } else if trimmed_line.starts_with("@if") {
-            if current_node.is_none() {
-                return Err(line_err("@if condition found outside of a node."));
-            }
-            if pending_condition.is_some() {
-                return Err(line_err("Consecutive @if conditions are not allowed."));
-            }
-            pending_condition = Some(trimmed_line[3..].trim().to_string());
-        } else if let Some(node) = &mut current_node {
-            if let Some(meta_line) = trimmed_line.strip_prefix('@') {
-                if pending_condition.is_some() {
-                    return Err(line_err(
-                        "@if must be immediately followed by a choice, not a meta/action line.",
-                    ));
-                }
-                if let Some(action_str) = meta_line.strip_prefix("action:") {
-                    node.actions.push(Action {
-                        command: action_str.trim().to_string(),
-                    });
-                } else if let Some(next_str) = meta_line.strip_prefix("next:") {
-                    if node.next.is_some() {
-                        return Err(line_err("Duplicate @next directive found."));
-                    }
-                    if !node.choices.is_empty() {
-                        return Err(line_err("@next cannot be used in a node that already has choices."));
-                    }
-                    node.next = Some(next_str.trim().to_string());
-                } else if let Some(colon_index) = meta_line.find(':') {
-                    let key = meta_line[..colon_index].trim().to_string();
-                    let value = meta_line[colon_index + 1..].trim().to_string();
-                    node.meta.insert(key, value);
-                } else {
-                    return Err(line_err(&format!(
-                        "Invalid meta or action line: {}",
-                        trimmed_line
-                    )));
-                }
-            } else if trimmed_line.starts_with('#') {
-                node.tags.extend(
-                    trimmed_line
-                        .split_whitespace()
-                        .filter_map(|s| {
-                            let tag = s.strip_prefix('#').unwrap_or(s);
-                            if tag.is_empty() {
-                                None
-                            } else {
-                                Some(tag.to_string())
-                            }
-                        })
-                );
-            } else if let Some(choice_line) = trimmed_line.strip_prefix('*') {
-                if node.next.is_some() {
-                    return Err(line_err("Choices cannot be added to a node that has a @next directive."));
-                }
-                let parts: Vec<&str> = choice_line.split("=>").map(|s| s.trim()).collect();
-                if parts.len() != 2 {
-                    return Err(line_err(&format!("Invalid choice format: {}", choice_line)));
-                }
+impl Default for Dialogue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-                let text = parts[0].to_string();
-                let rest = parts[1];
+/// The serialized form of a `Dialogue`, checked before it becomes one.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct DialogueData {
+    nodes: HashMap<String, Node>,
+    /// When missing, nodes are ordered by name.
+    order: Option<Vec<String>>,
+    start: Option<String>,
+    start_span: Option<Span>,
+    start_namespace: Option<String>,
+}
 
-                let (target_node_str, same_line_condition) = if let Some(if_index) = rest.find("@if")
-                {
-                    let target = rest[..if_index].trim().to_string();
-                    let cond = rest[if_index + 3..].trim().to_string();
-                    (target, Some(cond))
-                } else {
-                    (rest.to_string(), None)
-                };
+/// Rejects data whose `order` does not list every node exactly once, or
+/// whose nodes are filed under a name other than their own.
+#[cfg(feature = "serde")]
+impl TryFrom<DialogueData> for Dialogue {
+    type Error = String;
 
-                if same_line_condition.is_some() && pending_condition.is_some() {
-                    return Err(line_err(
-                        "A choice cannot have both a preceding @if and an inline @if.",
-                    ));
+    fn try_from(data: DialogueData) -> Result<Self, String> {
+        if let Some((key, node)) = data.nodes.iter().find(|(key, node)| **key != node.name) {
+            return Err(format!("node '{}' is stored under '{}'", node.name, key));
+        }
+        let order = match data.order {
+            Some(order) => {
+                let mut seen = std::collections::HashSet::new();
+                if let Some(name) = order.iter().find(|name| !seen.insert(name.as_str())) {
+                    return Err(format!("node '{}' appears twice in order", name));
                 }
-
-                let final_condition = same_line_condition.or_else(|| pending_condition.take());
-
-                node.choices.push(Choice {
-                    text,
-                    target_node: target_node_str,
-                    condition: final_condition,
-                });
-            } else {
-                if pending_condition.is_some() {
-                    return Err(line_err(
-                        "@if must be immediately followed by a choice, not body text.",
-                    ));
+                if let Some(name) = order.iter().find(|name| !data.nodes.contains_key(*name)) {
+                    return Err(format!("order names unknown node '{}'", name));
                 }
-                if !node.body.is_empty() {
-                    node.body.push('\n');
+                if let Some(name) = data.nodes.keys().find(|name| !seen.contains(name.as_str())) {
+                    return Err(format!("node '{}' is missing from order", name));
                 }
-                node.body.push_str(line);
+                order
             }
-        } else {
-            return Err(line_err(
-                "Content found outside of a node declaration. Every line must belong to a node starting with '::'.",
-            ));
-        }
-    }
-
-    if let Some(node) = current_node.take() {
-        if pending_condition.is_some() {
-            return Err("Dangling @if condition at end of file.".to_string());
-        }
-        dialogue.nodes.insert(node.name.clone(), node);
+            None => {
+                let mut order: Vec<String> = data.nodes.keys().cloned().collect();
+                order.sort();
+                order
+            }
+        };
+        Ok(Dialogue {
+            nodes: data.nodes,
+            order,
+            start: data.start,
+            start_span: data.start_span,
+            start_namespace: data.start_namespace,
+        })
     }
-
-    Ok(dialogue)
 }
 
 #[cfg(test)]
@@ -219,8 +340,8 @@ Hello, world!
 * Go next => next_node
         "#;
         let dialogue = parse(script).unwrap();
-        assert_eq!(dialogue.nodes.len(), 1);
-        let node = dialogue.nodes.get("start").unwrap();
+        assert_eq!(dialogue.len(), 1);
+        let node = dialogue.get("start").unwrap();
         assert_eq!(node.name, "start");
         assert_eq!(node.meta.get("who").unwrap(), "NPC");
         assert_eq!(node.body.trim(), "Hello, world!");
@@ -254,9 +375,9 @@ Really? I would be grateful for your help!
         "#;
 
         let dialogue = parse(script).unwrap();
-        assert_eq!(dialogue.nodes.len(), 2);
+        assert_eq!(dialogue.len(), 2);
 
-        let start_node = dialogue.nodes.get("start").unwrap();
+        let start_node = dialogue.get("start").unwrap();
         assert_eq!(start_node.name, "start");
         assert_eq!(start_node.meta.get("who").unwrap(), "NPC");
         assert_eq!(start_node.actions.len(), 1);
@@ -265,7 +386,7 @@ Really? I would be grateful for your help!
         assert_eq!(start_node.choices.len(), 2);
         assert_eq!(start_node.choices[0].target_node, "ask_help");
 
-        let ask_help_node = dialogue.nodes.get("ask_help").unwrap();
+        let ask_help_node = dialogue.get("ask_help").unwrap();
         assert_eq!(ask_help_node.name, "ask_help");
         assert_eq!(ask_help_node.choices.len(), 2);
         let conditional_choice = &ask_help_node.choices[1];
@@ -283,7 +404,7 @@ This is the first line.
 * A choice => somewhere
         "#;
         let dialogue = parse(script).unwrap();
-        let node = dialogue.nodes.get("multiline").unwrap();
+        let node = dialogue.get("multiline").unwrap();
         assert_eq!(node.body, "This is the first line.\n    This is the second line, with indentation.");
     }
     
@@ -305,7 +426,7 @@ Hello
 * choice => next
         "#;
         let dialogue = parse(script).unwrap();
-        let node = dialogue.nodes.get("start").unwrap();
+        let node = dialogue.get("start").unwrap();
         assert_eq!(node.tags, vec!["tag1", "tag2", "another_tag"]);
     }
 
@@ -316,11 +437,11 @@ Hello
         let result = parse(&script);
         assert!(result.is_ok(), "Parsing failed with: {:?}", result.err());
         let dialogue = result.unwrap();
-        assert_eq!(dialogue.nodes.len(), 4);
-        assert!(dialogue.nodes.contains_key("start"));
-        assert!(dialogue.nodes.contains_key("offer_help"));
-        assert!(dialogue.nodes.contains_key("ask_for_reward"));
-        assert!(dialogue.nodes.contains_key("end_final"));
+        assert_eq!(dialogue.len(), 4);
+        assert!(dialogue.contains("start"));
+        assert!(dialogue.contains("offer_help"));
+        assert!(dialogue.contains("ask_for_reward"));
+        assert!(dialogue.contains("end_final"));
     }
 
     #[test]
@@ -330,12 +451,12 @@ Hello
         let result = parse(&script);
         assert!(result.is_ok(), "Parsing failed with: {:?}", result.err());
         let dialogue = result.unwrap();
-        assert_eq!(dialogue.nodes.len(), 4);
-        assert!(dialogue.nodes.contains_key("start"));
-        assert!(dialogue.nodes.contains_key("end_final"));
-        let ask_for_reward_node = dialogue.nodes.get("ask_for_reward").unwrap();
+        assert_eq!(dialogue.len(), 4);
+        assert!(dialogue.contains("start"));
+        assert!(dialogue.contains("end_final"));
+        let ask_for_reward_node = dialogue.get("ask_for_reward").unwrap();
         assert_eq!(ask_for_reward_node.choices.len(), 3);
-        let offer_help_node = dialogue.nodes.get("offer_help").unwrap();
+        let offer_help_node = dialogue.get("offer_help").unwrap();
         assert_eq!(offer_help_node.actions.len(), 1);
     }
 
@@ -351,7 +472,7 @@ This is the second node.
 * Go to end => end_node
         "#;
         let dialogue = parse(script).unwrap();
-        let start_node = dialogue.nodes.get("start").unwrap();
+        let start_node = dialogue.get("start").unwrap();
         assert_eq!(start_node.next, Some("second_node".to_string()));
         assert!(start_node.choices.is_empty());
     }
@@ -363,7 +484,7 @@ This is the second node.
 @next: some_node
 * A choice => another_node
         "#;
-        assert!(parse(script).is_err());
+        assert!(matches!(parse(script), Err(ParseError::ChoiceWithNext { .. })));
     }
 
     #[test]
@@ -373,6 +494,400 @@ This is the second node.
 * A choice => another_node
 @next: some_node
         "#;
-        assert!(parse(script).is_err());
+        assert!(matches!(parse(script), Err(ParseError::NextWithChoices { .. })));
+    }
+
+    #[test]
+    fn test_error_span_points_at_line() {
+        let script = "::start\nHello\n  * Broken choice\n";
+        let err = parse(script).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::InvalidChoiceFormat {
+                choice: " Broken choice".to_string(),
+                span: Span {
+                    line: 3,
+                    column: 3,
+                    start: 16,
+                    end: 31,
+                },
+            }
+        );
+        assert_eq!(&script[err.span().range()], "* Broken choice");
+        assert_eq!(
+            err.to_string(),
+            "Error on line 3, column 3: Invalid choice format:  Broken choice"
+        );
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let dangling = parse("::start\n@if gold > 1\n\n::next\n").unwrap_err();
+        assert_eq!(
+            dangling,
+            ParseError::DanglingIf {
+                span: Span { line: 2, column: 1, start: 8, end: 20 },
+            }
+        );
+
+        let outside = parse("\r\nhello\r\n::start\r\n").unwrap_err();
+        assert_eq!(
+            outside,
+            ParseError::ContentOutsideNode {
+                span: Span { line: 2, column: 1, start: 2, end: 7 },
+            }
+        );
+
+        let duplicate = parse("::start\n@next: a\n@next: b\n").unwrap_err();
+        assert!(matches!(duplicate, ParseError::DuplicateNext { .. }));
+        assert_eq!(duplicate.line(), 3);
+    }
+
+    #[test]
+    fn test_parse_with_diagnostics_collects_all_errors() {
+        let script = r#"
+stray text
+more stray text
+::start
+Hello
+* Broken choice
+@next
+* Fine => end
+@if gold > 3
+
+::
+ignored body
+* ignored => start
+
+::end
+The end.
+@if dangling
+"#;
+        let output = parse_with_diagnostics(script);
+        let kinds: Vec<(usize, &str)> = output
+            .errors
+            .iter()
+            .map(|err| {
+                let kind = match err {
+                    ParseError::ContentOutsideNode { .. } => "outside",
+                    ParseError::InvalidChoiceFormat { .. } => "choice",
+                    ParseError::InvalidDirective { .. } => "directive",
+                    ParseError::DanglingIf { .. } => "dangling",
+                    ParseError::EmptyNodeName { .. } => "empty",
+                    _ => "other",
+                };
+                (err.line(), kind)
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (2, "outside"),
+                (6, "choice"),
+                (7, "directive"),
+                (9, "dangling"),
+                (11, "empty"),
+                (17, "dangling"),
+            ]
+        );
+
+        let dialogue = &output.dialogue;
+        assert_eq!(dialogue.len(), 2);
+        let start = dialogue.get("start").unwrap();
+        assert_eq!(start.body, "Hello");
+        assert_eq!(start.choices.len(), 1);
+        assert_eq!(start.choices[0].target_node, "end");
+        assert_eq!(dialogue.get("end").unwrap().body, "The end.");
+    }
+
+    #[test]
+    fn test_parse_returns_first_diagnostic() {
+        let script = "::start\n@bogus\n* nope\n";
+        let output = parse_with_diagnostics(script);
+        assert_eq!(output.errors.len(), 2);
+        assert_eq!(parse(script).unwrap_err(), output.errors[0]);
+        assert!(!parse_with_diagnostics("::start\nHi\n").has_errors());
+    }
+
+    #[test]
+    fn test_parse_records_spans() {
+        let script = "::start\n@who: NPC\n@action: set x = 1\n#intro #calm\nHello\n  there\n@if x > 0\n* Go => next_node\n";
+        let output = parse_with_options(
+            script,
+            &ParseOptions {
+                file: Some("scene.va".to_string()),
+                ..ParseOptions::default()
+            },
+        );
+        let node = output.dialogue.get("start").unwrap();
+        let text = |span: Span| &script[span.range()];
+
+        assert_eq!(node.file.as_deref(), Some("scene.va"));
+        assert_eq!(text(node.spans.header), "::start");
+        assert_eq!(text(node.spans.name), "start");
+        assert_eq!(node.spans.name.column, 3);
+        assert_eq!(text(node.spans.meta["who"]), "@who: NPC");
+        assert_eq!(text(node.actions[0].span), "@action: set x = 1");
+        assert_eq!(node.actions[0].span.line, 3);
+        let tags: Vec<&str> = node.spans.tags.iter().map(|span| text(*span)).collect();
+        assert_eq!(tags, vec!["#intro", "#calm"]);
+        assert_eq!(text(node.spans.body.unwrap()), "Hello\n  there");
+
+        let choice = &node.choices[0];
+        assert_eq!(text(choice.span), "* Go => next_node");
+        assert_eq!(text(choice.target_span), "next_node");
+        assert_eq!(choice.target_span.column, 9);
+        assert_eq!(text(choice.condition_span.unwrap()), "x > 0");
+        assert_eq!(choice.condition_span.unwrap().line, 7);
+    }
+
+    #[test]
+    fn test_equality_ignores_spans() {
+        let compact = parse("::start\nHi\n* Go => end @if a\n@next_time: soon\n").unwrap();
+        let spaced = parse("// header\n\n::start\n\nHi\n\n* Go   =>   end   @if   a\n@next_time: soon\n").unwrap();
+        assert_eq!(compact, spaced);
+        assert_ne!(
+            compact["start"].choices[0].span,
+            spaced["start"].choices[0].span
+        );
+    }
+
+    #[test]
+    fn test_duplicate_node_is_an_error() {
+        let script = "::start\nFirst\n\n::start\nSecond\n* Go => start\n";
+        let output = parse_with_diagnostics(script);
+        assert_eq!(
+            output.errors,
+            vec![ParseError::DuplicateNode {
+                name: "start".to_string(),
+                first: Span { line: 1, column: 1, start: 0, end: 7 },
+                span: Span { line: 4, column: 1, start: 15, end: 22 },
+            }]
+        );
+        let start = output.dialogue.get("start").unwrap();
+        assert_eq!(start.body, "First");
+        assert!(start.choices.is_empty());
+        assert_eq!(
+            output.errors[0].to_string(),
+            "Error on line 4, column 1: Node 'start' is already declared on line 1."
+        );
+    }
+
+    #[test]
+    fn test_duplicate_node_override_policy() {
+        let script = "::start\nFirst\n\n::start\nSecond\n";
+        let options = ParseOptions {
+            duplicate_nodes: DuplicateNodePolicy::Override,
+            ..ParseOptions::default()
+        };
+        let output = parse_with_options(script, &options);
+        assert!(!output.has_errors());
+        assert_eq!(output.dialogue.len(), 1);
+        assert_eq!(output.dialogue["start"].body, "Second");
+    }
+
+    #[test]
+    fn test_nodes_keep_declaration_order() {
+        let script = "::zeta\nZ\n::alpha\nA\n::mid\nM\n";
+        let dialogue = parse(script).unwrap();
+        let names: Vec<&str> = dialogue.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, vec!["zeta", "alpha", "mid"]);
+        assert_eq!(dialogue.order(), ["zeta", "alpha", "mid"]);
+        assert_eq!(dialogue.entry_node().unwrap().name, "zeta");
+        assert_eq!(Dialogue::new().entry_node(), None);
+
+        let mut edited = dialogue.clone();
+        let zeta = edited.remove("zeta").unwrap();
+        assert_eq!(edited.order(), ["alpha", "mid"]);
+        assert_eq!(edited.entry_node().unwrap().name, "alpha");
+        assert_eq!(edited.remove("zeta"), None);
+        edited.get_mut("mid").unwrap().body = "M2".to_string();
+        edited.insert_node(zeta);
+        assert_eq!(edited.order(), ["alpha", "mid", "zeta"]);
+        assert_eq!((edited.len(), edited["mid"].body.as_str()), (3, "M2"));
+        assert_ne!(edited, dialogue);
+    }
+
+    #[test]
+    fn test_start_directive() {
+        let script = "// Scene 1\n@start: alpha\n\n::zeta\nZ\n::alpha\nA\n";
+        let dialogue = parse(script).unwrap();
+        assert_eq!(dialogue.start.as_deref(), Some("alpha"));
+        assert_eq!(&script[dialogue.start_span.unwrap().range()], "alpha");
+        assert_eq!(dialogue.entry_node().unwrap().name, "alpha");
+
+        let twice = parse("@start: a\n@start: b\n::a\n").unwrap_err();
+        assert!(matches!(twice, ParseError::DuplicateStart { .. }));
+        assert_eq!(twice.line(), 2);
+
+        let late = parse("::a\n@start: a\n").unwrap();
+        assert_eq!(late.start, None);
+        assert_eq!(late["a"].meta["start"], "a");
+    }
+
+    #[test]
+    fn test_parse_condition_expression() {
+        let script = "::start\n@if gold >= 10 and not angry\n* Pay => shop\n* Beg => shop @if (mood)\n";
+        let dialogue = parse(script).unwrap();
+        let choices = &dialogue["start"].choices;
+        assert_eq!(
+            choices[0].condition_expr,
+            Some(parse_expr("gold >= 10 and not angry").unwrap())
+        );
+        assert_eq!(choices[1].condition_expr, Some(Expr::Variable("mood".to_string())));
+    }
+
+    #[test]
+    fn test_invalid_condition_reports_position() {
+        let script = "::start\n* Pay => shop @if gold = 10\n";
+        let err = parse(script).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::InvalidCondition {
+                message: "Unexpected '='; use '==' for comparison".to_string(),
+                span: Span { line: 2, column: 24, start: 31, end: 32 },
+            }
+        );
+        assert_eq!(&script[err.span().range()], "=");
+
+        let err = parse("::start\n@if (gold\n* Pay => shop\n").unwrap_err();
+        assert!(matches!(err, ParseError::InvalidCondition { .. }));
+        assert_eq!((err.line(), err.column()), (2, 5));
+    }
+
+    #[test]
+    fn test_parse_typed_actions() {
+        let script = "::start\n@action: set help_requested = 1\n@action: play_sound bell.wav\n";
+        let dialogue = parse(script).unwrap();
+        let actions = &dialogue["start"].actions;
+        assert_eq!(
+            actions[0].kind,
+            ActionKind::Set {
+                variable: "help_requested".to_string(),
+                value: Expr::Literal(Value::Int(1)),
+            }
+        );
+        assert_eq!(
+            actions[1].kind,
+            ActionKind::Custom {
+                name: "play_sound".to_string(),
+                args: "bell.wav".to_string(),
+            }
+        );
+
+        let err = parse("::start\n@action: set gold = = 2\n").unwrap_err();
+        assert!(matches!(err, ParseError::InvalidAction { .. }));
+        assert_eq!((err.line(), err.column()), (2, 21));
+    }
+
+    #[test]
+    fn test_parse_text_interpolation() {
+        let script = "::start\nHello, {player_name}!\n  {{not a variable}}\n* Pay {price} gold => shop\n";
+        let node = &parse(script).unwrap()["start"];
+        assert_eq!(
+            node.body_segments,
+            vec![
+                Segment::Literal("Hello, ".to_string()),
+                Segment::Expr(Expr::Variable("player_name".to_string())),
+                Segment::Literal("!".to_string()),
+                Segment::Literal("\n".to_string()),
+                Segment::Literal("  {not a variable}".to_string()),
+            ]
+        );
+        assert_eq!(node.choices[0].text, "Pay {price} gold");
+        assert_eq!(node.choices[0].text_segments.len(), 3);
+
+        let err = parse("::start\nHi {name\n").unwrap_err();
+        assert!(matches!(err, ParseError::InvalidInterpolation { .. }));
+        assert_eq!((err.line(), err.column()), (2, 4));
+        let err = parse("::start\n* Buy {gold +} => shop\n").unwrap_err();
+        assert_eq!((err.line(), err.column()), (2, 14));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_round_trip() {
+        let script = fs::read_to_string("examples/varion_long_example.vion").unwrap();
+        let dialogue = parse(&script).unwrap();
+        let json = serde_json::to_string(&dialogue).unwrap();
+        let restored: Dialogue = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, dialogue);
+        assert_eq!(restored["start"].spans, dialogue["start"].spans);
+
+        let dialogue = parse("::start\n@action: gold += 2\n* Pay => shop @if gold >= 1.5\n").unwrap();
+        let json = serde_json::to_value(&dialogue["start"]).unwrap();
+        assert_eq!(
+            json["actions"][0]["kind"],
+            serde_json::json!({ "add": { "variable": "gold", "amount": { "literal": 2 } } })
+        );
+        assert_eq!(
+            json["choices"][0]["condition_expr"],
+            serde_json::json!({ "binary": {
+                "op": "ge",
+                "left": { "variable": "gold" },
+                "right": { "literal": 1.5 },
+            } })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_spans_are_optional() {
+        let json = r#"{
+            "nodes": { "start": {
+                "name": "start",
+                "meta": {},
+                "next": null,
+                "actions": [],
+                "tags": [],
+                "body": "Hi.",
+                "body_segments": [{ "literal": "Hi." }],
+                "choices": [{
+                    "text": "Go",
+                    "text_segments": [{ "literal": "Go" }],
+                    "target_node": "start",
+                    "condition": null,
+                    "condition_expr": null
+                }]
+            } },
+            "order": ["start"],
+            "start": null
+        }"#;
+        let dialogue: Dialogue = serde_json::from_str(json).unwrap();
+        assert_eq!(dialogue, parse("::start\nHi.\n* Go => start\n").unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_checks_order() {
+        let node = |name: &str| {
+            serde_json::to_value(&parse(&format!("::{}\n", name)).unwrap()[name]).unwrap()
+        };
+        let dialogue = |order: serde_json::Value| {
+            let json = serde_json::json!({
+                "nodes": { "b": node("b"), "a": node("a") },
+                "order": order,
+                "start": null,
+            });
+            serde_json::from_value::<Dialogue>(json)
+        };
+        assert_eq!(
+            dialogue(serde_json::json!(["b", "a"])).unwrap().order(),
+            ["b", "a"]
+        );
+        assert_eq!(
+            dialogue(serde_json::Value::Null).unwrap().order(),
+            ["a", "b"]
+        );
+        for order in [
+            serde_json::json!(["ghost", "ghost"]),
+            serde_json::json!(["a", "b", "ghost"]),
+            serde_json::json!(["a"]),
+        ] {
+            assert!(dialogue(order).is_err());
+        }
+        let json =
+            serde_json::json!({ "nodes": { "b": node("a") }, "order": ["b"], "start": null });
+        assert!(serde_json::from_value::<Dialogue>(json).is_err());
     }
 }
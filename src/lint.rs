@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::{Dialogue, Node, Span};
+
+/// A structural problem found by `Dialogue::lint`.
+///
+/// Unlike `ValidationError`s, lints do not break the dialogue; they point
+/// at places where a player may be stranded or content is never shown.
+/// Unresolved targets are left to `Dialogue::validate` and ignored here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    /// A node that cannot be reached from the entry node.
+    Unreachable { node: String, span: Span },
+    /// A node with no choices and no `@next`, not tagged as an ending.
+    DeadEnd { node: String, span: Span },
+    /// A group of nodes that only lead into each other, except through
+    /// conditional choices.
+    NoExitCycle {
+        /// The nodes in the cycle, in declaration order.
+        nodes: Vec<String>,
+        /// Whether a conditional choice leads out of the cycle.
+        conditional_exit: bool,
+        /// The name of the first node in the cycle.
+        span: Span,
+    },
+    /// A node whose choices all have conditions, so it ends the dialogue
+    /// when none of them hold.
+    AllChoicesConditional { node: String, span: Span },
+}
+
+impl Lint {
+    /// Returns a short, stable identifier for the kind of lint.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::Unreachable { .. } => "unreachable",
+            Lint::DeadEnd { .. } => "dead-end",
+            Lint::NoExitCycle { .. } => "no-exit-cycle",
+            Lint::AllChoicesConditional { .. } => "all-choices-conditional",
+        }
+    }
+
+    /// Returns the name of the node the lint is about; for a cycle, its
+    /// first node.
+    pub fn node(&self) -> &str {
+        match self {
+            Lint::Unreachable { node, .. }
+            | Lint::DeadEnd { node, .. }
+            | Lint::AllChoicesConditional { node, .. } => node,
+            Lint::NoExitCycle { nodes, .. } => &nodes[0],
+        }
+    }
+
+    /// Returns the location of the node name the lint is about.
+    pub fn span(&self) -> Span {
+        match self {
+            Lint::Unreachable { span, .. }
+            | Lint::DeadEnd { span, .. }
+            | Lint::NoExitCycle { span, .. }
+            | Lint::AllChoicesConditional { span, .. } => *span,
+        }
+    }
+
+    /// Returns the message without location information.
+    pub fn message(&self) -> String {
+        match self {
+            Lint::Unreachable { node, .. } => {
+                format!("Node '{}' cannot be reached from the entry node.", node)
+            }
+            Lint::DeadEnd { node, .. } => format!(
+                "Node '{}' has no choices or @next but is not tagged as an ending.",
+                node
+            ),
+            Lint::NoExitCycle {
+                nodes,
+                conditional_exit,
+                ..
+            } => {
+                let names: Vec<String> = nodes.iter().map(|node| format!("'{}'", node)).collect();
+                let subject = match &names[..] {
+                    [name] => format!("Node {} is a loop", name),
+                    _ => format!("Nodes {} form a cycle", names.join(", ")),
+                };
+                if *conditional_exit {
+                    format!("{} that can only be left when a condition holds.", subject)
+                } else {
+                    format!("{} with no exit.", subject)
+                }
+            }
+            Lint::AllChoicesConditional { node, .. } => format!(
+                "Every choice in node '{}' is conditional, so the dialogue ends if none is available.",
+                node
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(
+            f,
+            "Warning on line {}, column {}: {}",
+            span.line,
+            span.column,
+            self.message()
+        )
+    }
+}
+
+/// Options for `Dialogue::lint_with_options`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintOptions {
+    /// Tags, without `#`, that mark a node as an intended ending.
+    pub ending_tags: Vec<String>,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        LintOptions {
+            ending_tags: vec!["end".to_string(), "ending".to_string()],
+        }
+    }
+}
+
+/// A way out of a node, as the runner takes it: the node's choices, or its
+/// `@next` when it has none. `target` is the resolved node name.
+struct Exit<'a> {
+    target: &'a str,
+    conditional: bool,
+}
+
+fn exits<'a>(dialogue: &'a Dialogue, node: &'a Node) -> Vec<Exit<'a>> {
+    let exits: Vec<Exit> = if node.choices.is_empty() {
+        node.next
+            .iter()
+            .map(|next| Exit {
+                target: next,
+                conditional: false,
+            })
+            .collect()
+    } else {
+        node.choices
+            .iter()
+            .map(|choice| Exit {
+                target: &choice.target_node,
+                conditional: choice.condition.is_some(),
+            })
+            .collect()
+    };
+    exits
+        .into_iter()
+        .filter_map(|exit| {
+            let target = dialogue.resolve_from(node, exit.target)?;
+            Some(Exit {
+                target: &target.name,
+                ..exit
+            })
+        })
+        .collect()
+}
+
+impl Dialogue {
+    /// Looks for structural problems with the default `LintOptions`.
+    pub fn lint(&self) -> Vec<Lint> {
+        self.lint_with_options(&LintOptions::default())
+    }
+
+    /// Looks for unreachable nodes, untagged dead ends, cycles without an
+    /// unconditional exit and nodes whose every choice is conditional.
+    ///
+    /// Lints are ordered by the node they are about, in declaration order.
+    pub fn lint_with_options(&self, options: &LintOptions) -> Vec<Lint> {
+        let exits: HashMap<&str, Vec<Exit>> = self
+            .iter()
+            .map(|node| (node.name.as_str(), exits(self, node)))
+            .collect();
+
+        let mut reachable = HashSet::new();
+        if let Some(entry) = self.entry_node() {
+            let mut stack = vec![entry.name.as_str()];
+            while let Some(name) = stack.pop() {
+                if reachable.insert(name) {
+                    stack.extend(
+                        exits
+                            .get(name)
+                            .into_iter()
+                            .flatten()
+                            .map(|exit| exit.target),
+                    );
+                }
+            }
+        }
+
+        // Each trapping cycle is reported on its first node.
+        let mut cycles: HashMap<&str, Lint> = HashMap::new();
+        for component in components(self, &exits) {
+            let is_cycle = component.len() > 1
+                || exits
+                    .get(component[0])
+                    .into_iter()
+                    .flatten()
+                    .any(|exit| exit.target == component[0]);
+            if !is_cycle {
+                continue;
+            }
+            let leaving = component
+                .iter()
+                .flat_map(|name| exits.get(name).into_iter().flatten())
+                .filter(|exit| !component.contains(&exit.target));
+            let mut conditional_exit = false;
+            let mut trapped = true;
+            for exit in leaving {
+                if exit.conditional {
+                    conditional_exit = true;
+                } else {
+                    trapped = false;
+                }
+            }
+            if trapped {
+                let first = component[0];
+                cycles.insert(
+                    first,
+                    Lint::NoExitCycle {
+                        nodes: component.iter().map(|name| name.to_string()).collect(),
+                        conditional_exit,
+                        span: self.nodes[first].spans.name,
+                    },
+                );
+            }
+        }
+
+        let mut lints = Vec::new();
+        for node in self.iter() {
+            let (name, span) = (node.name.clone(), node.spans.name);
+            // Without an entry node there is nothing to be reachable from.
+            if !reachable.is_empty() && !reachable.contains(node.name.as_str()) {
+                lints.push(Lint::Unreachable {
+                    node: name.clone(),
+                    span,
+                });
+            }
+            if node.choices.is_empty()
+                && node.next.is_none()
+                && !node
+                    .tags
+                    .iter()
+                    .any(|tag| options.ending_tags.contains(tag))
+            {
+                lints.push(Lint::DeadEnd {
+                    node: name.clone(),
+                    span,
+                });
+            }
+            if !node.choices.is_empty()
+                && node.choices.iter().all(|choice| choice.condition.is_some())
+            {
+                lints.push(Lint::AllChoicesConditional { node: name, span });
+            }
+            if let Some(cycle) = cycles.remove(node.name.as_str()) {
+                lints.push(cycle);
+            }
+        }
+        lints
+    }
+}
+
+/// Splits the nodes into strongly connected components with Tarjan's
+/// algorithm. Each component lists its nodes in declaration order.
+///
+/// The depth-first search keeps its own stack, so long chains of nodes do
+/// not overflow the thread's stack.
+fn components<'a>(
+    dialogue: &'a Dialogue,
+    exits: &HashMap<&'a str, Vec<Exit<'a>>>,
+) -> Vec<Vec<&'a str>> {
+    struct Tarjan<'a> {
+        index: HashMap<&'a str, usize>,
+        low: HashMap<&'a str, usize>,
+        stack: Vec<&'a str>,
+        on_stack: HashSet<&'a str>,
+        components: Vec<Vec<&'a str>>,
+    }
+
+    impl<'a> Tarjan<'a> {
+        /// Starts visiting `name`.
+        fn open(&mut self, name: &'a str) {
+            let index = self.index.len();
+            self.index.insert(name, index);
+            self.low.insert(name, index);
+            self.stack.push(name);
+            self.on_stack.insert(name);
+        }
+
+        fn lower(&mut self, name: &'a str, low: usize) {
+            if low < self.low[name] {
+                self.low.insert(name, low);
+            }
+        }
+
+        /// Finishes visiting `name` once all its exits are done, popping its
+        /// component if it is the component's root.
+        fn close(&mut self, name: &'a str) {
+            if self.low[name] != self.index[name] {
+                return;
+            }
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(member);
+                component.push(member);
+                if member == name {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        index: HashMap::new(),
+        low: HashMap::new(),
+        stack: Vec::new(),
+        on_stack: HashSet::new(),
+        components: Vec::new(),
+    };
+    for node in dialogue.iter() {
+        if tarjan.index.contains_key(node.name.as_str()) {
+            continue;
+        }
+        tarjan.open(&node.name);
+        // Each entry is a node being visited and the index of its next exit.
+        let mut work: Vec<(&str, usize)> = vec![(&node.name, 0)];
+        while let Some((name, next)) = work.last_mut() {
+            let name = *name;
+            let exit = exits.get(name).and_then(|exits| exits.get(*next));
+            *next += 1;
+            match exit.map(|exit| exit.target) {
+                Some(target) if !tarjan.index.contains_key(target) => {
+                    tarjan.open(target);
+                    work.push((target, 0));
+                }
+                Some(target) => {
+                    if tarjan.on_stack.contains(target) {
+                        let low = tarjan.index[target];
+                        tarjan.lower(name, low);
+                    }
+                }
+                None => {
+                    work.pop();
+                    if let Some(&(parent, _)) = work.last() {
+                        let low = tarjan.low[name];
+                        tarjan.lower(parent, low);
+                    }
+                    tarjan.close(name);
+                }
+            }
+        }
+    }
+
+    let position: HashMap<&str, usize> = dialogue
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (node.name.as_str(), index))
+        .collect();
+    for component in &mut tarjan.components {
+        component.sort_by_key(|name| position.get(name));
+    }
+    tarjan.components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn summary(dialogue: &Dialogue, options: &LintOptions) -> Vec<(&'static str, String)> {
+        dialogue
+            .lint_with_options(options)
+            .iter()
+            .map(|lint| (lint.code(), lint.message()))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let script = "\
+::start
+* Ask => ask
+* Wait => start @if patience > 0
+
+::ask
+* Demand more => ask
+* Threaten => threaten
+* Leave => done @if gold > 10
+
+::threaten
+@next: ask
+
+::done
+#end
+Bye.
+
+::forgotten
+* Hello? => forgotten @if lonely
+";
+        let dialogue = parse(script).unwrap();
+        let lints = dialogue.lint();
+        assert_eq!(
+            lints[0],
+            Lint::NoExitCycle {
+                nodes: vec!["ask".to_string(), "threaten".to_string()],
+                conditional_exit: true,
+                span: dialogue["ask"].spans.name,
+            }
+        );
+        assert_eq!(
+            lints[0].to_string(),
+            "Warning on line 5, column 3: Nodes 'ask', 'threaten' form a cycle \
+             that can only be left when a condition holds."
+        );
+        assert_eq!(
+            summary(&dialogue, &LintOptions::default())[1..],
+            [
+                (
+                    "unreachable",
+                    "Node 'forgotten' cannot be reached from the entry node.".to_string()
+                ),
+                (
+                    "all-choices-conditional",
+                    "Every choice in node 'forgotten' is conditional, so the dialogue \
+                     ends if none is available."
+                        .to_string()
+                ),
+                (
+                    "no-exit-cycle",
+                    "Node 'forgotten' is a loop with no exit.".to_string()
+                ),
+            ]
+        );
+
+        let options = LintOptions {
+            ending_tags: Vec::new(),
+        };
+        assert!(summary(&dialogue, &options).contains(&(
+            "dead-end",
+            "Node 'done' has no choices or @next but is not tagged as an ending.".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_lint_long_chain() {
+        let mut script = String::new();
+        for i in 0..20_000 {
+            script.push_str(&format!("::n{}\n@next: n{}\n", i, i + 1));
+        }
+        script.push_str("::n20000\n#end\n");
+        let dialogue = parse(&script).unwrap();
+        assert_eq!(dialogue.lint(), Vec::new());
+    }
+
+    #[test]
+    fn test_lint_ignores_unresolved_targets() {
+        let dialogue = parse("::start\n* Go => nowhere\n").unwrap();
+        assert_eq!(dialogue.lint(), Vec::new());
+        assert_eq!(Dialogue::new().lint(), Vec::new());
+    }
+}
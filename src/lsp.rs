@@ -0,0 +1,1163 @@
+//! A Language Server Protocol server for Varion scripts.
+//!
+//! Enabled by the `lsp` feature. The `varion-lsp` binary runs it over stdio;
+//! `Server::handle` can also be driven in-process, e.g. from tests.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::namespace::qualify;
+use crate::rename::{load, node_references, resolved, resolved_references};
+use crate::{
+    parse_cst, parse_project_with_loader, rename_node_in_files, Cst, Dialogue, FileLoader, Include,
+    LineKind, ParseError, ParseOptions, ProjectError, ProjectOutput, SourceLoader, Span,
+    ValidationError,
+};
+
+const ERROR_SEVERITY: u32 = 1;
+const REFERENCE_COMPLETION_KIND: u32 = 18;
+const MODULE_SYMBOL_KIND: u32 = 2;
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// How many body lines a hover shows.
+const HOVER_BODY_LINES: usize = 3;
+
+/// Converts between byte offsets and LSP positions, whose `character` counts
+/// UTF-16 code units.
+struct LineIndex {
+    /// Byte offset of the start of each line.
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { starts }
+    }
+
+    fn position(&self, text: &str, offset: usize) -> Value {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = text[self.starts[line]..offset].encode_utf16().count();
+        json!({ "line": line, "character": character })
+    }
+
+    fn offset(&self, text: &str, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let start = *self.starts.get(line)?;
+        let end = self.starts.get(line + 1).copied().unwrap_or(text.len());
+        let mut units = 0;
+        for (i, c) in text[start..end].char_indices() {
+            if units >= character || c == '\r' || c == '\n' {
+                return Some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        Some(end)
+    }
+
+    fn range(&self, text: &str, span: Span) -> Value {
+        json!({
+            "start": self.position(text, span.start),
+            "end": self.position(text, span.end),
+        })
+    }
+}
+
+/// An open script and everything derived from it.
+struct Document {
+    text: String,
+    index: LineIndex,
+    cst: Cst,
+    dialogue: Dialogue,
+    errors: Vec<ParseError>,
+    includes: Vec<Include>,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let cst = parse_cst(&text);
+        let output = cst.to_dialogue();
+        Document {
+            index: LineIndex::new(&text),
+            text,
+            cst,
+            dialogue: output.dialogue,
+            errors: output.errors,
+            includes: output.includes,
+        }
+    }
+
+    fn range(&self, span: Span) -> Value {
+        self.index.range(&self.text, span)
+    }
+
+    /// Returns the node name declared or referenced at `offset`, with the
+    /// span it was found in. References are resolved among the nodes of
+    /// `project`, which may hold nodes from other files.
+    fn resolved_name_at<'a>(
+        &'a self,
+        offset: usize,
+        project: &'a Dialogue,
+    ) -> Option<(&'a str, Span)> {
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+        self.dialogue
+            .iter()
+            .map(|node| (node.name.as_str(), node.spans.name))
+            .chain(resolved_references(&self.dialogue, project))
+            .find(|(_, span)| contains(span))
+    }
+
+    /// Returns the `@namespace` of the script.
+    fn namespace(&self) -> Option<&str> {
+        self.cst.lines().find_map(|line| match line.kind {
+            LineKind::Namespace { name: Some(name) } => Some(line.slice(name)),
+            _ => None,
+        })
+    }
+}
+
+/// A Varion language server.
+///
+/// Supports full-text document sync, diagnostics from the parser and
+/// `Dialogue::validate`, go-to-definition, find-references, hover, node
+/// name completion after `=>`, `@next:` and `@start:`, document symbols,
+/// and renaming a node across the document, its includes and open
+/// documents that include it. Nodes declared in `@include`d scripts are
+/// resolved, read from disk or from their unsaved text when open.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutting_down: bool,
+    exited: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` once the client has sent `exit`.
+    pub fn is_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Handles one JSON-RPC message, returning the messages to send back:
+    /// the response to a request, and any diagnostics it produced.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let Some(method) = message["method"].as_str() else {
+            // A response to a request we never send.
+            return Vec::new();
+        };
+        let params = &message["params"];
+        let Some(id) = message.get("id") else {
+            return self.notification(method, params);
+        };
+        let response = match self.request(method, params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, error)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": error },
+            }),
+        };
+        vec![response]
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.shutting_down {
+            return Err((INVALID_REQUEST, "The server is shutting down".to_string()));
+        }
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": [">", ":"] },
+                    "documentSymbolProvider": true,
+                    "renameProvider": true,
+                },
+                "serverInfo": { "name": "varion-lsp", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutting_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/rename" => self.rename(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Full sync: the last change holds the whole text.
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, Vec::new())];
+            }
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            }
+            _ => return Vec::new(),
+        };
+        let Some(text) = text else {
+            return Vec::new();
+        };
+        let document = Document::new(text.to_string());
+        let diagnostics = diagnostics(uri, &document);
+        self.documents.insert(uri.to_string(), document);
+        vec![publish_diagnostics(uri, diagnostics)]
+    }
+
+    /// Looks up the document and byte offset named by a position request.
+    fn locate<'a>(
+        &'a self,
+        params: &'a Value,
+    ) -> Result<(&'a str, &'a Document, usize), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document '{}'", uri)))?;
+        let offset = document
+            .index
+            .offset(&document.text, &params["position"])
+            .ok_or_else(|| (INVALID_PARAMS, "Invalid position".to_string()))?;
+        Ok((uri, document, offset))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, offset) = self.locate(params)?;
+        let project = self.project(uri, document);
+        let dialogue = project.as_ref().map_or(&document.dialogue, |p| &p.dialogue);
+        let node = document
+            .resolved_name_at(offset, dialogue)
+            .and_then(|(name, _)| dialogue.get(name));
+        Ok(node
+            .and_then(|node| self.location(uri, document, node.file.as_deref(), node.spans.name))
+            .unwrap_or(Value::Null))
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, offset) = self.locate(params)?;
+        let project = self.project(uri, document);
+        let dialogue = project.as_ref().map_or(&document.dialogue, |p| &p.dialogue);
+        let Some((name, _)) = document.resolved_name_at(offset, dialogue) else {
+            return Ok(Value::Null);
+        };
+        let mut locations = Vec::new();
+        if params["context"]["includeDeclaration"].as_bool() == Some(true) {
+            if let Some(node) = dialogue.get(name) {
+                locations.extend(self.location(
+                    uri,
+                    document,
+                    node.file.as_deref(),
+                    node.spans.name,
+                ));
+            }
+        }
+        // `@start:` is in the script the project recorded for it, or in the
+        // document itself.
+        let start_file = project
+            .as_ref()
+            .and_then(|p| p.start_file.as_ref())
+            .map(|file| file.display().to_string());
+        let start = dialogue.start.as_deref().zip(dialogue.start_span);
+        if let Some((start, span)) = start {
+            let namespace = dialogue.start_namespace.as_deref();
+            if resolved(dialogue, namespace, start) == name {
+                locations.extend(self.location(uri, document, start_file.as_deref(), span));
+            }
+        }
+        for node in dialogue.iter() {
+            for (target, span) in node_references(node, dialogue) {
+                if target == name {
+                    locations.extend(self.location(uri, document, node.file.as_deref(), span));
+                }
+            }
+        }
+        Ok(Value::Array(locations))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, offset) = self.locate(params)?;
+        let project = self.project(uri, document);
+        let dialogue = project.as_ref().map_or(&document.dialogue, |p| &p.dialogue);
+        let Some((node, span)) = document
+            .resolved_name_at(offset, dialogue)
+            .and_then(|(name, span)| Some((dialogue.get(name)?, span)))
+        else {
+            return Ok(Value::Null);
+        };
+
+        let mut value = format!("**::{}**", node.name);
+        let mut meta: Vec<_> = node.meta.iter().collect();
+        meta.sort();
+        if !meta.is_empty() {
+            value.push('\n');
+            for (key, meta_value) in meta {
+                value.push_str(&format!("\n- `{}`: {}", key, meta_value));
+            }
+        }
+        let lines: Vec<&str> = node.body.lines().map(str::trim).collect();
+        if !lines.is_empty() {
+            value.push('\n');
+            for line in lines.iter().take(HOVER_BODY_LINES) {
+                value.push_str(&format!("\n> {}", line));
+            }
+            if lines.len() > HOVER_BODY_LINES {
+                value.push_str("\n> …");
+            }
+        }
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": value },
+            "range": document.range(span),
+        }))
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, offset) = self.locate(params)?;
+        let line_start = document.text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let before = document.text[line_start..offset].trim_start();
+        let expects_name = match before.strip_prefix('*') {
+            Some(choice) => choice
+                .rfind("=>")
+                .is_some_and(|arrow| !choice[arrow..].contains("@if")),
+            None => before.starts_with("@next:") || before.starts_with("@start:"),
+        };
+        if !expects_name {
+            return Ok(json!([]));
+        }
+        // Nodes in the namespace the name is written in are offered by their
+        // local name, others by their full name.
+        let namespace = document.namespace();
+        let project = self.project(uri, document);
+        let dialogue = project.as_ref().map_or(&document.dialogue, |p| &p.dialogue);
+        let items: Vec<Value> = dialogue
+            .iter()
+            .map(|node| {
+                let label = if node.namespace.as_deref() == namespace {
+                    node.local_name()
+                } else {
+                    &node.name
+                };
+                json!({ "label": label, "kind": REFERENCE_COMPLETION_KIND })
+            })
+            .collect();
+        Ok(Value::Array(items))
+    }
+
+    fn rename(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, offset) = self.locate(params)?;
+        let files = self.rename_scope(uri, document);
+        let sources: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(uri, text)| (uri.as_str(), text.as_str()))
+            .collect();
+        let named: Vec<_> = sources
+            .iter()
+            .map(|(uri, text)| (Some(uri.to_string()), *text))
+            .collect();
+        let (_, project) = load(&named).map_err(|err| (REQUEST_FAILED, err.to_string()))?;
+        let Some((name, _)) = document.resolved_name_at(offset, &project) else {
+            return Err((REQUEST_FAILED, "No node name here".to_string()));
+        };
+        // Editors offer the name as written, so the new name is always a
+        // local name in the node's namespace, even one like `a.b` inside
+        // `@namespace a`.
+        let namespace = project.get(name).and_then(|node| node.namespace.as_deref());
+        let new_name = qualify(namespace, params["newName"].as_str().unwrap_or_default());
+        let edits = rename_node_in_files(&sources, name, &new_name)
+            .map_err(|err| (REQUEST_FAILED, err.to_string()))?;
+
+        let mut changes = serde_json::Map::new();
+        for (uri, text) in &files {
+            let index = LineIndex::new(text);
+            let file_edits: Vec<Value> = edits
+                .iter()
+                .filter(|edit| edit.file.as_deref() == Some(uri.as_str()))
+                .map(|edit| json!({ "range": index.range(text, edit.span), "newText": edit.new_text }))
+                .collect();
+            if !file_edits.is_empty() {
+                changes.insert(uri.clone(), Value::Array(file_edits));
+            }
+        }
+        Ok(json!({ "changes": changes }))
+    }
+
+    /// Returns the scripts a rename in `document` covers, as `(uri, text)`
+    /// pairs: the document and the scripts it includes, and every open
+    /// document whose includes reach it, with the scripts that one
+    /// includes. Open documents are read from their unsaved text, the rest
+    /// from disk.
+    fn rename_scope(&self, uri: &str, document: &Document) -> Vec<(String, String)> {
+        let Some(path) = uri_path(uri) else {
+            return vec![(uri.to_string(), document.text.clone())];
+        };
+        let mut open: Vec<(PathBuf, &str)> = self
+            .documents
+            .keys()
+            .filter_map(|uri| Some((uri_path(uri)?, uri.as_str())))
+            .collect();
+        open.sort();
+        let mut loader = self.loader();
+
+        let mut paths = vec![path.clone()];
+        let others = open
+            .iter()
+            .map(|(path, _)| path)
+            .filter(|other| **other != path);
+        for root in std::iter::once(&path).chain(others) {
+            let output = parse_project_with_loader(root, &mut loader, &ParseOptions::default());
+            if output.files.contains(&path) {
+                for file in output.files {
+                    if !paths.contains(&file) {
+                        paths.push(file);
+                    }
+                }
+            }
+        }
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                let text = loader.load(&path).ok()?;
+                let uri = match open.iter().find(|(open, _)| *open == path) {
+                    Some((_, uri)) => uri.to_string(),
+                    None => file_uri(&path),
+                };
+                Some((uri, text))
+            })
+            .collect()
+    }
+
+    /// Parses `document` with the scripts it includes, so that nodes
+    /// declared in them resolve. Returns `None` if the document includes
+    /// nothing or is not a local file.
+    fn project(&self, uri: &str, document: &Document) -> Option<ProjectOutput> {
+        let path = uri_path(uri).filter(|_| !document.includes.is_empty())?;
+        let output = parse_project_with_loader(path, &mut self.loader(), &ParseOptions::default());
+        Some(output)
+    }
+
+    /// Returns a loader that reads open documents from their unsaved text.
+    fn loader(&self) -> DocumentLoader<'_> {
+        DocumentLoader {
+            texts: self
+                .documents
+                .iter()
+                .filter_map(|(uri, document)| Some((uri_path(uri)?, document.text.as_str())))
+                .collect(),
+        }
+    }
+
+    /// Returns the LSP location of `span` in `file`, the script a node was
+    /// parsed from in a project, or in `document` if there is none. Returns
+    /// `None` if the script can no longer be read.
+    fn location(
+        &self,
+        uri: &str,
+        document: &Document,
+        file: Option<&str>,
+        span: Span,
+    ) -> Option<Value> {
+        let Some(path) = file.map(PathBuf::from) else {
+            return Some(json!({ "uri": uri, "range": document.range(span) }));
+        };
+        let open = self
+            .documents
+            .iter()
+            .find(|(uri, _)| uri_path(uri).as_ref() == Some(&path));
+        if let Some((uri, document)) = open {
+            return Some(json!({ "uri": uri, "range": document.range(span) }));
+        }
+        let text = FileLoader.load(&path).ok()?;
+        let range = LineIndex::new(&text).range(&text, span);
+        Some(json!({ "uri": file_uri(&path), "range": range }))
+    }
+
+    fn document_symbols(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document '{}'", uri)))?;
+        let mut symbols = Vec::new();
+        for node in &document.cst.nodes {
+            let LineKind::Header { name } = node.header.kind else {
+                continue;
+            };
+            if name.start == name.end {
+                continue;
+            }
+            let end = node
+                .lines
+                .iter()
+                .rev()
+                .find(|line| !matches!(line.kind, LineKind::Blank | LineKind::Comment))
+                .unwrap_or(&node.header)
+                .span
+                .end;
+            let range = Span {
+                end,
+                ..node.header.span
+            };
+            symbols.push(json!({
+                "name": node.header.slice(name),
+                "kind": MODULE_SYMBOL_KIND,
+                "range": document.range(range),
+                "selectionRange": document.range(name),
+            }));
+        }
+        Ok(Value::Array(symbols))
+    }
+}
+
+fn diagnostics(uri: &str, document: &Document) -> Vec<Value> {
+    let diagnostic = |span: Span, message: String| {
+        json!({
+            "range": document.range(span),
+            "severity": ERROR_SEVERITY,
+            "source": "varion",
+            "message": message,
+        })
+    };
+    let mut diagnostics: Vec<Value> = document
+        .errors
+        .iter()
+        .map(|err| diagnostic(err.span(), err.message()))
+        .collect();
+    let path = uri_path(uri).filter(|_| !document.includes.is_empty());
+    let validation = match path {
+        Some(path) => validate_with_includes(&path, document),
+        None => match document.dialogue.validate() {
+            Ok(()) => (Vec::new(), Vec::new()),
+            Err(errors) => (Vec::new(), errors),
+        },
+    };
+    let (project_errors, validation_errors) = validation;
+    diagnostics.extend(
+        project_errors
+            .iter()
+            .filter_map(|err| Some(diagnostic(err.span()?, err.message()))),
+    );
+    diagnostics.extend(
+        validation_errors
+            .iter()
+            .map(|err| diagnostic(err.span(), err.message())),
+    );
+    diagnostics
+}
+
+/// Reads open documents from their unsaved text, and other scripts from
+/// disk.
+struct DocumentLoader<'a> {
+    texts: HashMap<PathBuf, &'a str>,
+}
+
+impl SourceLoader for DocumentLoader<'_> {
+    fn load(&mut self, path: &Path) -> io::Result<String> {
+        match self.texts.get(path) {
+            Some(text) => Ok(text.to_string()),
+            None => FileLoader.load(path),
+        }
+    }
+}
+
+/// Validates a document against the scripts it includes, so that nodes
+/// declared in them resolve. Returns the include and validation errors
+/// located in the document itself.
+fn validate_with_includes(
+    path: &Path,
+    document: &Document,
+) -> (Vec<ProjectError>, Vec<ValidationError>) {
+    let mut loader = DocumentLoader {
+        texts: HashMap::from([(path.to_path_buf(), document.text.as_str())]),
+    };
+    let output = parse_project_with_loader(path, &mut loader, &ParseOptions::default());
+    let Some(root) = output.files.last() else {
+        return (Vec::new(), Vec::new());
+    };
+    let file = root.display().to_string();
+    let in_document = |node: &str| {
+        output.dialogue.get(node).and_then(|n| n.file.as_deref()) == Some(file.as_str())
+    };
+
+    let project_errors = output
+        .errors
+        .iter()
+        // Parse errors in the document are already reported by `Document`.
+        .filter(|err| !matches!(err, ProjectError::Parse { .. }) && err.file() == root)
+        .cloned()
+        .collect();
+    let validation_errors = match output.dialogue.validate() {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .into_iter()
+            .filter(|err| match err {
+                ValidationError::UnresolvedTarget { node, .. }
+                | ValidationError::UnresolvedNext { node, .. } => in_document(node),
+                ValidationError::UnresolvedStart { .. } => output.start_file.as_ref() == Some(root),
+            })
+            .collect(),
+    };
+    (project_errors, validation_errors)
+}
+
+/// Returns the local path of a `file:` URI, decoding `%XX` escapes.
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let escape = (encoded[i] == b'%')
+            .then(|| std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// Returns the `file:` URI of a local path, escaping bytes other than
+/// unreserved characters and `/` as `%XX`.
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.to_string_lossy().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Reads one `Content-Length`-framed message, or `None` at end of input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    read_body(reader)?
+        .map(|body| {
+            serde_json::from_slice(&body)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .transpose()
+}
+
+/// Reads the body of one `Content-Length`-framed message, or `None` at end
+/// of input.
+fn read_body(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// Writes one message with a `Content-Length` header.
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Serves requests from `reader` until the client sends `exit` or closes
+/// the stream. A message that is not valid JSON gets a parse error
+/// response; only I/O errors and broken framing end the session.
+pub fn run(mut reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(body) = read_body(&mut reader)? {
+        let replies = match serde_json::from_slice(&body) {
+            Ok(message) => server.handle(&message),
+            // The id of a malformed message cannot be read.
+            Err(err) => vec![json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": PARSE_ERROR, "message": err.to_string() },
+            })],
+        };
+        for reply in replies {
+            write_message(&mut writer, &reply)?;
+        }
+        if server.is_exited() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///guard.va";
+
+    /// A minimal in-process client.
+    struct Client {
+        server: Server,
+        next_id: u64,
+    }
+
+    impl Client {
+        fn new() -> Self {
+            let mut client = Client {
+                server: Server::new(),
+                next_id: 0,
+            };
+            client.request("initialize", json!({ "capabilities": {} }));
+            client
+        }
+
+        fn request(&mut self, method: &str, params: Value) -> Value {
+            self.next_id += 1;
+            let replies = self.server.handle(&json!({
+                "jsonrpc": "2.0",
+                "id": self.next_id,
+                "method": method,
+                "params": params,
+            }));
+            assert_eq!(replies.len(), 1);
+            assert_eq!(replies[0]["id"], self.next_id);
+            replies[0].clone()
+        }
+
+        fn notify(&mut self, method: &str, params: Value) -> Vec<Value> {
+            self.server
+                .handle(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+        }
+
+        fn at(&mut self, method: &str, line: u32, character: u32) -> Value {
+            self.request(
+                method,
+                json!({
+                    "textDocument": { "uri": URI },
+                    "position": { "line": line, "character": character },
+                    "context": { "includeDeclaration": true },
+                }),
+            )["result"]
+                .clone()
+        }
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> Value {
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        })
+    }
+
+    const SCRIPT: &str = "\
+::경비병
+@who: Guard
+Stop right there!
+Who goes there?
+Speak up.
+And quickly.
+* Pay => pass
+* Run => 도망 @if gold < 1
+
+::pass
+@next: 경비병
+
+::도망
+The end.
+";
+
+    #[test]
+    fn test_lsp_diagnostics() {
+        let mut client = Client::new();
+        let replies = client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "varion", "version": 1,
+                "text": "::start\n* Go => nowhere\n* Bad => start @if gold =\n" } }),
+        );
+        assert_eq!(replies[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = replies[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0]["range"], range(2, 24, 25));
+        assert_eq!(diagnostics[1]["range"], range(1, 8, 15));
+        assert!(diagnostics[1]["message"]
+            .as_str()
+            .unwrap()
+            .contains("nowhere"));
+
+        let replies = client.notify(
+            "textDocument/didChange",
+            json!({ "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": SCRIPT }] }),
+        );
+        assert_eq!(replies[0]["params"]["diagnostics"], json!([]));
+
+        let replies = client.notify(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": URI } }),
+        );
+        assert_eq!(replies[0]["params"]["diagnostics"], json!([]));
+        let reply = client.at("textDocument/hover", 0, 0);
+        assert_eq!(reply, Value::Null);
+    }
+
+    #[test]
+    fn test_lsp_diagnostics_follow_includes() {
+        let dir = std::env::temp_dir().join(format!("varion-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("scenes")).unwrap();
+        std::fs::write(dir.join("scenes/shop.va"), "::shop\n@next: nowhere\n").unwrap();
+        let uri = format!("file://{}/main%20scene.va", dir.display());
+
+        let mut client = Client::new();
+        let replies = client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": uri, "languageId": "varion", "version": 1,
+                "text": "@include \"scenes/shop.va\"\n@include \"gone.va\"\n::hub\n* Shop => shop\n* Lost => lost\n" } }),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // `shop` resolves, and the broken `@next` in shop.va is not reported here.
+        let diagnostics = replies[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 2, "{:#?}", diagnostics);
+        assert_eq!(diagnostics[0]["range"], range(1, 0, 18));
+        assert!(diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Cannot read"));
+        assert_eq!(diagnostics[1]["range"], range(4, 10, 14));
+    }
+
+    #[test]
+    fn test_lsp_navigation() {
+        let mut client = Client::new();
+        client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "text": SCRIPT } }),
+        );
+
+        // `=> 도망` jumps to `::도망`; columns count UTF-16 units.
+        let definition = client.at("textDocument/definition", 7, 10);
+        assert_eq!(definition, json!({ "uri": URI, "range": range(12, 2, 4) }));
+        assert_eq!(client.at("textDocument/definition", 2, 3), Value::Null);
+
+        let references = client.at("textDocument/references", 0, 3);
+        let ranges: Vec<&Value> = references
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| &location["range"])
+            .collect();
+        assert_eq!(ranges, vec![&range(0, 2, 5), &range(10, 7, 10)]);
+
+        let hover = client.at("textDocument/hover", 10, 8);
+        assert_eq!(
+            hover["contents"]["value"],
+            "**::경비병**\n\n- `who`: Guard\n\n> Stop right there!\n> Who goes there?\n> Speak up.\n> …"
+        );
+
+        let symbols = client.request(
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": URI } }),
+        )["result"]
+            .clone();
+        let names: Vec<&Value> = symbols
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|symbol| &symbol["name"])
+            .collect();
+        assert_eq!(names, vec!["경비병", "pass", "도망"]);
+        assert_eq!(
+            symbols[0]["range"]["end"],
+            json!({ "line": 7, "character": 24 })
+        );
+    }
+
+    #[test]
+    fn test_lsp_navigation_follows_includes() {
+        let dir = std::env::temp_dir().join(format!("varion-lsp-nav-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("shop.va"),
+            "@namespace shop\n::counter\n@who: Clerk\nWelcome.\n* Leave => village.start\n",
+        )
+        .unwrap();
+        let uri = format!("file://{}/main.va", dir.display());
+        let shop = format!("file://{}/shop.va", dir.display());
+
+        let mut client = Client::new();
+        client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": uri,
+                "text": "@include \"shop.va\"\n@namespace village\n::start\n* Buy => shop.counter\n* Stay => \n" } }),
+        );
+        let mut at = |method: &str, line: u32, character: u32| {
+            client.request(
+                method,
+                json!({
+                    "textDocument": { "uri": uri },
+                    "position": { "line": line, "character": character },
+                    "context": { "includeDeclaration": true },
+                }),
+            )["result"]
+                .clone()
+        };
+        let definition = at("textDocument/definition", 3, 12);
+        let references = at("textDocument/references", 2, 3);
+        let hover = at("textDocument/hover", 3, 12);
+        let completion = at("textDocument/completion", 4, 10);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(definition, json!({ "uri": shop, "range": range(1, 2, 9) }));
+        assert_eq!(
+            references,
+            json!([
+                { "uri": uri, "range": range(2, 2, 7) },
+                { "uri": shop, "range": range(4, 11, 24) },
+            ])
+        );
+        assert_eq!(
+            hover["contents"]["value"],
+            "**::shop.counter**\n\n- `who`: Clerk\n\n> Welcome."
+        );
+        let labels: Vec<&Value> = completion
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| &item["label"])
+            .collect();
+        assert_eq!(labels, vec!["shop.counter", "start"]);
+    }
+
+    #[test]
+    fn test_lsp_completion() {
+        let mut client = Client::new();
+        let text = "::start\n* Go => \n@next: \nHello\n";
+        client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "text": text } }),
+        );
+        let labels = |result: Value| -> Vec<String> {
+            result
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["label"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            labels(client.at("textDocument/completion", 1, 8)),
+            vec!["start"]
+        );
+        assert_eq!(
+            labels(client.at("textDocument/completion", 2, 7)),
+            vec!["start"]
+        );
+        assert!(labels(client.at("textDocument/completion", 3, 2)).is_empty());
+
+        let text = "@namespace village\n::start\n* Go => \n\n::hub\n@next: \n";
+        client.notify(
+            "textDocument/didChange",
+            json!({ "textDocument": { "uri": URI }, "contentChanges": [{ "text": text }] }),
+        );
+        assert_eq!(
+            labels(client.at("textDocument/completion", 2, 8)),
+            vec!["start", "hub"]
+        );
+    }
+
+    #[test]
+    fn test_lsp_errors_and_shutdown() {
+        let mut client = Client::new();
+        let reply = client.request("workspace/symbol", json!({}));
+        assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+        let reply = client.request(
+            "textDocument/hover",
+            json!({ "textDocument": { "uri": "file:///missing.va" }, "position": { "line": 0, "character": 0 } }),
+        );
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+
+        assert_eq!(
+            client.request("shutdown", Value::Null)["result"],
+            Value::Null
+        );
+        assert_eq!(
+            client.request("textDocument/documentSymbol", json!({}))["error"]["code"],
+            INVALID_REQUEST
+        );
+        client.notify("exit", Value::Null);
+        assert!(client.server.is_exited());
+    }
+
+    #[test]
+    fn test_lsp_stdio_transport() {
+        let mut input = Vec::new();
+        for message in [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": URI, "text": "::start\n" } } }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+        ] {
+            write_message(&mut input, &message).unwrap();
+        }
+        let mut output = Vec::new();
+        run(Cursor::new(input), &mut output).unwrap();
+
+        let mut reader = Cursor::new(output);
+        let first = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(first["result"]["serverInfo"]["name"], "varion-lsp");
+        let second = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(second["params"]["diagnostics"], json!([]));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_lsp_transport_survives_invalid_json() {
+        let mut input = b"Content-Length: 8\r\n\r\n{\"id\": 1".to_vec();
+        for message in [
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ] {
+            write_message(&mut input, &message).unwrap();
+        }
+        let mut output = Vec::new();
+        run(Cursor::new(input), &mut output).unwrap();
+
+        let mut reader = Cursor::new(output);
+        let first = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(first["id"], Value::Null);
+        assert_eq!(first["error"]["code"], PARSE_ERROR);
+        let second = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(second["id"], 2);
+        assert_eq!(second["result"]["serverInfo"]["name"], "varion-lsp");
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let mut input = Cursor::new(b"Content-Type: json\r\n\r\n{}".to_vec());
+        assert!(run(&mut input, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_lsp_rename() {
+        let mut client = Client::new();
+        client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "text": SCRIPT } }),
+        );
+        let rename = |client: &mut Client, new_name: &str| {
+            client.request(
+                "textDocument/rename",
+                json!({
+                    "textDocument": { "uri": URI },
+                    "position": { "line": 7, "character": 10 },
+                    "newName": new_name,
+                }),
+            )
+        };
+        let reply = rename(&mut client, "flee");
+        assert_eq!(
+            reply["result"]["changes"][URI],
+            json!([
+                { "range": range(7, 9, 11), "newText": "flee" },
+                { "range": range(12, 2, 4), "newText": "flee" },
+            ])
+        );
+        let reply = rename(&mut client, "pass");
+        assert_eq!(reply["error"]["code"], REQUEST_FAILED);
+    }
+
+    #[test]
+    fn test_lsp_rename_across_files() {
+        let dir = std::env::temp_dir().join(format!("varion-lsp-rename-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("town.va"), "@namespace town\n::hub\n@next: hub\n").unwrap();
+        let uri = format!("file://{}/main.va", dir.display());
+        let town = format!("file://{}/town.va", dir.display());
+
+        let mut client = Client::new();
+        client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": uri,
+                "text": "@include \"town.va\"\n@namespace village\n::start\n* Go => town.hub\n" } }),
+        );
+        let mut rename = |new_name: &str| {
+            client.request(
+                "textDocument/rename",
+                json!({
+                    "textDocument": { "uri": uri },
+                    "position": { "line": 3, "character": 10 },
+                    "newName": new_name,
+                }),
+            )["result"]["changes"]
+                .clone()
+        };
+        let square = rename("square");
+        // The new name is local to `town`, even when it contains a dot.
+        let dotted = rename("town.square");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            square,
+            json!({
+                uri.clone(): [{ "range": range(3, 8, 16), "newText": "town.square" }],
+                town.clone(): [
+                    { "range": range(1, 2, 5), "newText": "square" },
+                    { "range": range(2, 7, 10), "newText": "square" },
+                ],
+            })
+        );
+        assert_eq!(dotted[&uri][0]["newText"], "town.town.square");
+        assert_eq!(dotted[&town][0]["newText"], "town.square");
+    }
+}
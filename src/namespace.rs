@@ -0,0 +1,150 @@
+use crate::{Dialogue, Node};
+
+impl Node {
+    /// Returns the name as declared after `::`, without the namespace.
+    pub fn local_name(&self) -> &str {
+        self.namespace
+            .as_deref()
+            .and_then(|namespace| self.name.strip_prefix(namespace)?.strip_prefix('.'))
+            .unwrap_or(&self.name)
+    }
+}
+
+/// Joins a namespace and a local node name.
+pub(crate) fn qualify(namespace: Option<&str>, name: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}.{}", namespace, name),
+        None => name.to_string(),
+    }
+}
+
+/// Returns the node names a target written in `namespace` may refer to, in
+/// the order they are tried: inside the namespace first, then as written.
+pub(crate) fn candidates(namespace: Option<&str>, target: &str) -> Vec<String> {
+    match namespace {
+        Some(_) => vec![qualify(namespace, target), target.to_string()],
+        None => vec![target.to_string()],
+    }
+}
+
+impl Dialogue {
+    /// Finds the node that `target`, written in a script with the given
+    /// `@namespace`, refers to.
+    ///
+    /// `target` is looked up inside the namespace first, so `=> start` in
+    /// `@namespace village` finds `village.start`, and then as written, so
+    /// `=> town.start` reaches into another namespace.
+    pub fn resolve(&self, namespace: Option<&str>, target: &str) -> Option<&Node> {
+        candidates(namespace, target)
+            .iter()
+            .find_map(|name| self.get(name))
+    }
+
+    /// Finds the node a choice target or `@next` in `node` refers to.
+    pub fn resolve_from(&self, node: &Node, target: &str) -> Option<&Node> {
+        self.resolve(node.namespace.as_deref(), target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::{parse, parse_project_with_loader, DialogueRunner, ParseOptions};
+
+    #[test]
+    fn test_namespaces() {
+        let dialogue = parse(
+            "\
+@namespace village
+@start: start
+
+::start
+* Stay => end
+* Travel => town.start
+* Nowhere => nowhere
+
+::end
+",
+        )
+        .unwrap();
+        assert_eq!(dialogue.order, vec!["village.start", "village.end"]);
+        let start = dialogue.entry_node().unwrap();
+        assert_eq!(start.name, "village.start");
+        assert_eq!(start.local_name(), "start");
+        assert_eq!(start.namespace.as_deref(), Some("village"));
+
+        let targets: Vec<_> = start
+            .choices
+            .iter()
+            .map(|choice| dialogue.resolve_from(start, &choice.target_node))
+            .map(|node| node.map(|node| node.name.as_str()))
+            .collect();
+        assert_eq!(targets, vec![Some("village.end"), None, None]);
+        assert_eq!(
+            dialogue.resolve(None, "village.end").unwrap().local_name(),
+            "end"
+        );
+        assert!(dialogue.resolve(None, "end").is_none());
+    }
+
+    #[test]
+    fn test_namespaces_across_files() {
+        let mut files: HashMap<PathBuf, String> = [
+            (
+                "main.va",
+                "@namespace village\n@start: start\n@include \"town.va\"\n\n::start\n* Travel => town.start\n",
+            ),
+            (
+                "town.va",
+                "@namespace town\n\n::start\n@next: back\n\n::back\n* Home => village.start\n* Lost => ned\n",
+            ),
+        ]
+        .into_iter()
+        .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+        .collect();
+        let dialogue =
+            parse_project_with_loader("main.va", &mut files, &ParseOptions::default()).dialogue;
+        assert_eq!(
+            dialogue.order,
+            vec!["town.start", "town.back", "village.start"]
+        );
+
+        let errors = dialogue.validate().unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Choice in node 'town.back' targets unknown node 'ned' \
+             (searched 'town.ned', 'ned')."
+        );
+
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        assert_eq!(runner.current_node().name, "village.start");
+        runner.advance().unwrap();
+        runner.choose(0).unwrap();
+        assert_eq!(runner.current_node().name, "town.start");
+
+        assert_eq!(
+            dialogue.to_string(),
+            "\
+@start: village.start
+
+::town.start
+@next: town.back
+
+::town.back
+* Home => village.start
+* Lost => ned
+
+::village.start
+* Travel => town.start
+"
+        );
+        assert_eq!(
+            dialogue["town.back"].to_string(),
+            "::back\n* Home => village.start\n* Lost => ned\n"
+        );
+        let single = parse("@namespace town\n\n::start\n@next: back\n\n::back\n").unwrap();
+        assert_eq!(parse(&single.to_string()).unwrap(), single);
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+
+use crate::namespace::qualify;
+use crate::{
+    parse_action, parse_cst, parse_expr, parse_text, Action, Choice, Cst, CstLine, Dialogue,
+    ExprError, Include, LineKind, Node, NodeSpans, ParseError, Segment, Span,
+};
+
+/// What to do when a node name is declared more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateNodePolicy {
+    /// Report `ParseError::DuplicateNode` and keep the first declaration.
+    #[default]
+    Error,
+    /// Let the later declaration replace the earlier one, as patch or mod
+    /// files expect.
+    Override,
+}
+
+/// Options controlling how a script is parsed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Identifies the script being parsed; copied into `Node::file`.
+    pub file: Option<String>,
+    /// How a node declared twice in one script is handled; see
+    /// `DuplicateNodePolicy`.
+    pub duplicate_nodes: DuplicateNodePolicy,
+}
+
+/// The result of parsing a script in recovering mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOutput {
+    /// Everything that could be parsed, skipping the lines that had errors.
+    pub dialogue: Dialogue,
+    /// Every error found, in source order.
+    pub errors: Vec<ParseError>,
+    /// The `@include` directives, in source order. They are followed by
+    /// `parse_project`, not when parsing a single script.
+    pub includes: Vec<Include>,
+}
+
+impl ParseOutput {
+    /// Returns `true` if any error was found.
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// Returns the dialogue, or the first error if there were any.
+    pub fn into_result(mut self) -> Result<Dialogue, ParseError> {
+        if self.errors.is_empty() {
+            Ok(self.dialogue)
+        } else {
+            Err(self.errors.remove(0))
+        }
+    }
+}
+
+/// Parses a Varion script string into a Dialogue struct.
+///
+/// # Arguments
+///
+/// * `script` - A string slice that holds the Varion script.
+///
+/// # Returns
+///
+/// * `Ok(Dialogue)` if parsing is successful.
+/// * `Err(ParseError)` describing the first problem found otherwise.
+pub fn parse(script: &str) -> Result<Dialogue, ParseError> {
+    parse_with_diagnostics(script).into_result()
+}
+
+/// Parses a Varion script, recovering from errors instead of stopping at the
+/// first one.
+///
+/// A line with an error is skipped. Errors that leave no node to attach
+/// content to (an unnamed `::` or content before the first node) skip ahead
+/// to the next `::` declaration. The returned dialogue contains every node
+/// that could be parsed.
+pub fn parse_with_diagnostics(script: &str) -> ParseOutput {
+    parse_with_options(script, &ParseOptions::default())
+}
+
+/// Parses a Varion script in recovering mode with the given options.
+pub fn parse_with_options(script: &str, options: &ParseOptions) -> ParseOutput {
+    parse_cst(script).to_dialogue_with_options(options)
+}
+
+/// Builds a `Dialogue` from a concrete syntax tree.
+pub(crate) fn lower(cst: &Cst, options: &ParseOptions) -> ParseOutput {
+    let mut parser = Parser {
+        options: options.clone(),
+        ..Parser::default()
+    };
+    for line in cst.lines() {
+        if let Err(err) = parser.parse_line(line) {
+            parser.errors.push(err);
+        }
+    }
+    parser.finish()
+}
+
+/// An `@if` line waiting for the choice it applies to.
+struct PendingCondition {
+    text: String,
+    /// The condition text after `@if`.
+    span: Span,
+    /// The whole `@if` line.
+    line_span: Span,
+}
+
+#[derive(Default)]
+struct Parser {
+    options: ParseOptions,
+    dialogue: Dialogue,
+    errors: Vec<ParseError>,
+    includes: Vec<Include>,
+    /// The `@namespace` of the script, once its line has been seen.
+    namespace: Option<String>,
+    current_node: Option<Node>,
+    pending_condition: Option<PendingCondition>,
+    /// Set after an error that leaves no node to attach content to; every
+    /// line up to the next `::` declaration is ignored.
+    skipping_to_node: bool,
+}
+
+impl Parser {
+    fn parse_line(&mut self, line: &CstLine) -> Result<(), ParseError> {
+        let span = line.content;
+        match &line.kind {
+            LineKind::Blank | LineKind::Comment => return Ok(()),
+            LineKind::Header { name } => {
+                self.skipping_to_node = false;
+                if let Err(err) = self.finish_node() {
+                    self.errors.push(err);
+                }
+
+                let local_name = line.slice(*name);
+                if local_name.is_empty() {
+                    self.skipping_to_node = true;
+                    return Err(ParseError::EmptyNodeName { span });
+                }
+                let node_name = qualify(self.namespace.as_deref(), local_name);
+                if let Some(existing) = self.dialogue.get(&node_name) {
+                    if self.options.duplicate_nodes == DuplicateNodePolicy::Error {
+                        self.skipping_to_node = true;
+                        return Err(ParseError::DuplicateNode {
+                            name: node_name,
+                            first: existing.spans.header,
+                            span,
+                        });
+                    }
+                }
+                self.current_node = Some(Node {
+                    name: node_name,
+                    namespace: self.namespace.clone(),
+                    meta: HashMap::new(),
+                    next: None,
+                    actions: Vec::new(),
+                    tags: Vec::new(),
+                    body: String::new(),
+                    body_segments: Vec::new(),
+                    choices: Vec::new(),
+                    file: self.options.file.clone(),
+                    spans: NodeSpans {
+                        header: span,
+                        name: *name,
+                        ..NodeSpans::default()
+                    },
+                });
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if self.skipping_to_node {
+            return Ok(());
+        }
+
+        let pending_condition = &mut self.pending_condition;
+        let Some(node) = &mut self.current_node else {
+            return self.parse_header_line(line);
+        };
+
+        match &line.kind {
+            LineKind::If { condition } => {
+                if pending_condition.is_some() {
+                    return Err(ParseError::ConsecutiveIf { span });
+                }
+                *pending_condition = Some(PendingCondition {
+                    text: line.slice(*condition).to_string(),
+                    span: *condition,
+                    line_span: span,
+                });
+            }
+            LineKind::Directive { key, value } => {
+                if pending_condition.take().is_some() {
+                    return Err(ParseError::IfBeforeDirective { span });
+                }
+                let Some(value) = *value else {
+                    return Err(ParseError::InvalidDirective {
+                        line: line.slice(span).to_string(),
+                        span,
+                    });
+                };
+                let value_str = line.slice(value);
+                match line.slice(*key) {
+                    "action" => {
+                        let kind =
+                            parse_action(value_str).map_err(|err| ParseError::InvalidAction {
+                                span: value.narrow(value_str, err.start, err.end),
+                                message: err.message,
+                            })?;
+                        node.actions.push(Action {
+                            command: value_str.to_string(),
+                            kind,
+                            span,
+                        });
+                    }
+                    "next" => {
+                        if node.next.is_some() {
+                            return Err(ParseError::DuplicateNext { span });
+                        }
+                        if !node.choices.is_empty() {
+                            return Err(ParseError::NextWithChoices { span });
+                        }
+                        node.next = Some(value_str.to_string());
+                        node.spans.next = Some(value);
+                    }
+                    key => {
+                        node.spans.meta.insert(key.to_string(), span);
+                        node.meta.insert(key.to_string(), value_str.to_string());
+                    }
+                }
+            }
+            LineKind::Tags { tags } => {
+                for tag_span in tags {
+                    let tag = line.slice(*tag_span);
+                    node.tags
+                        .push(tag.strip_prefix('#').unwrap_or(tag).to_string());
+                    node.spans.tags.push(*tag_span);
+                }
+            }
+            LineKind::Choice {
+                text,
+                target,
+                condition,
+            } => {
+                let preceding_condition = pending_condition.take();
+                if node.next.is_some() {
+                    return Err(ParseError::ChoiceWithNext { span });
+                }
+                let Some(target) = *target else {
+                    return Err(ParseError::InvalidChoiceFormat {
+                        choice: line.slice(*text).to_string(),
+                        span,
+                    });
+                };
+
+                let same_line_condition =
+                    condition.map(|condition| (line.slice(condition).to_string(), condition));
+                if same_line_condition.is_some() && preceding_condition.is_some() {
+                    return Err(ParseError::ConflictingConditions { span });
+                }
+
+                let final_condition = same_line_condition.or_else(|| {
+                    preceding_condition.map(|condition| (condition.text, condition.span))
+                });
+                let (condition, condition_span) = final_condition.unzip();
+                let condition_expr = match (&condition, condition_span) {
+                    (Some(condition), Some(condition_span)) => Some(
+                        parse_expr(condition).map_err(|err| ParseError::InvalidCondition {
+                            span: condition_span.narrow(condition, err.start, err.end),
+                            message: err.message,
+                        })?,
+                    ),
+                    _ => None,
+                };
+
+                let text_segments = parse_text(line.slice(*text))
+                    .map_err(|err| invalid_interpolation(line, *text, err))?;
+                node.choices.push(Choice {
+                    text: line.slice(*text).to_string(),
+                    text_segments,
+                    target_node: line.slice(target).to_string(),
+                    condition,
+                    condition_expr,
+                    span,
+                    target_span: target,
+                    condition_span,
+                });
+            }
+            LineKind::Include { .. } => return Err(ParseError::IncludeInsideNode { span }),
+            LineKind::Namespace { .. } => return Err(ParseError::NamespaceInsideNode { span }),
+            LineKind::Body => {
+                if pending_condition.take().is_some() {
+                    return Err(ParseError::IfBeforeBody { span });
+                }
+                let segments = parse_text(&line.text)
+                    .map_err(|err| invalid_interpolation(line, line.span, err))?;
+                if !node.body.is_empty() {
+                    node.body.push('\n');
+                    node.body_segments.push(Segment::Literal("\n".to_string()));
+                }
+                node.body.push_str(&line.text);
+                node.body_segments.extend(segments);
+                node.spans.body = Some(match node.spans.body {
+                    Some(body_span) => Span {
+                        end: line.span.end,
+                        ..body_span
+                    },
+                    None => line.span,
+                });
+            }
+            LineKind::Blank | LineKind::Comment | LineKind::Header { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Parses a line before the first node declaration, where only file-level
+    /// directives are allowed.
+    fn parse_header_line(&mut self, line: &CstLine) -> Result<(), ParseError> {
+        let span = line.content;
+        if let LineKind::Namespace { name } = line.kind {
+            let name = name.ok_or(ParseError::InvalidNamespace { span })?;
+            if self.namespace.is_some() {
+                return Err(ParseError::DuplicateNamespace { span });
+            }
+            self.namespace = Some(line.slice(name).to_string());
+            return Ok(());
+        }
+        if let LineKind::Include { path } = line.kind {
+            let path = path.ok_or(ParseError::InvalidInclude { span })?;
+            self.includes.push(Include {
+                path: line.slice(path).to_string(),
+                span,
+            });
+            return Ok(());
+        }
+        if let LineKind::Directive {
+            key,
+            value: Some(value),
+        } = line.kind
+        {
+            if line.slice(key) == "start" {
+                if self.dialogue.start.is_some() {
+                    return Err(ParseError::DuplicateStart { span });
+                }
+                self.dialogue.start = Some(line.slice(value).to_string());
+                self.dialogue.start_span = Some(value);
+                return Ok(());
+            }
+        }
+
+        self.skipping_to_node = true;
+        if matches!(line.kind, LineKind::If { .. }) {
+            return Err(ParseError::IfOutsideNode { span });
+        }
+        Err(ParseError::ContentOutsideNode { span })
+    }
+
+    /// Stores the node being parsed, reporting an `@if` left without a choice.
+    fn finish_node(&mut self) -> Result<(), ParseError> {
+        if let Some(node) = self.current_node.take() {
+            self.dialogue.insert_node(node);
+        }
+        match self.pending_condition.take() {
+            Some(condition) => Err(ParseError::DanglingIf {
+                span: condition.line_span,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(mut self) -> ParseOutput {
+        if let Err(err) = self.finish_node() {
+            self.errors.push(err);
+        }
+        if self.dialogue.start.is_some() {
+            self.dialogue.start_namespace = self.namespace;
+        }
+        ParseOutput {
+            dialogue: self.dialogue,
+            errors: self.errors,
+            includes: self.includes,
+        }
+    }
+}
+
+/// Converts an error from `parse_text` on the text at `span` in `line`.
+fn invalid_interpolation(line: &CstLine, span: Span, err: ExprError) -> ParseError {
+    ParseError::InvalidInterpolation {
+        span: span.narrow(line.slice(span), err.start, err.end),
+        message: err.message,
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+
+use crate::{Choice, Dialogue, Node};
+
+/// Writes the node as canonical Varion source: the `::name` header, meta
+/// entries sorted by key, actions, tags, body, choices and `@next:`.
+///
+/// The header uses the local name and targets are written as parsed, so a
+/// namespaced node reads back the same under its `@namespace`.
+///
+/// Body lines are written verbatim, so lines that would read as directives
+/// (starting with `@`, `#`, `*`, `::` or `//`) or blank lines do not survive
+/// a round trip; the parser never produces such bodies.
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_node(f, self, self.local_name(), |target| target.to_string())
+    }
+}
+
+/// Writes the whole dialogue as canonical Varion source, with nodes in
+/// declaration order separated by blank lines, such that parsing the output
+/// gives back an equal `Dialogue`.
+///
+/// When every node shares a namespace it is written as `@namespace`. A
+/// script has only one `@namespace`, so nodes from several namespaces, as
+/// merged by `parse_project`, are written with their full names and
+/// resolved targets instead. Parsing that output gives back the same nodes
+/// in the same order, with every target and `@start:` resolving to the same
+/// node, but with no `namespace` recorded on them.
+impl fmt::Display for Dialogue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let namespace = self.iter().next().map(|node| &node.namespace);
+        let shared = namespace.filter(|namespace| {
+            self.iter().all(|node| node.namespace == **namespace)
+                && (self.start.is_none() || self.start_namespace == **namespace)
+        });
+        let mut preamble = false;
+        if let Some(Some(namespace)) = shared {
+            writeln!(f, "@namespace {}", namespace)?;
+            preamble = true;
+        }
+        if let Some(start) = &self.start {
+            match shared {
+                Some(_) => writeln!(f, "@start: {}", start)?,
+                None => writeln!(
+                    f,
+                    "@start: {}",
+                    self.resolved(self.start_namespace.as_deref(), start)
+                )?,
+            }
+            preamble = true;
+        }
+        if preamble && !self.order.is_empty() {
+            writeln!(f)?;
+        }
+        for (i, node) in self.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match shared {
+                Some(_) => write!(f, "{}", node)?,
+                None => write_node(f, node, &node.name, |target| {
+                    self.resolved(node.namespace.as_deref(), target).to_string()
+                })?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Dialogue {
+    /// The full name `target` resolves to, or `target` if it does not.
+    fn resolved<'a>(&'a self, namespace: Option<&str>, target: &'a str) -> &'a str {
+        self.resolve(namespace, target)
+            .map_or(target, |node| node.name.as_str())
+    }
+}
+
+/// Writes a node under the header `name`, passing each target through
+/// `target`.
+fn write_node(
+    f: &mut fmt::Formatter<'_>,
+    node: &Node,
+    name: &str,
+    target: impl Fn(&str) -> String,
+) -> fmt::Result {
+    writeln!(f, "::{}", name)?;
+
+    let mut meta: Vec<_> = node.meta.iter().collect();
+    meta.sort();
+    for (key, value) in meta {
+        writeln!(f, "@{}: {}", key, value)?;
+    }
+    for action in &node.actions {
+        writeln!(f, "@action: {}", action.command)?;
+    }
+    if !node.tags.is_empty() {
+        let tags: Vec<String> = node.tags.iter().map(|tag| format!("#{}", tag)).collect();
+        writeln!(f, "{}", tags.join(" "))?;
+    }
+    for line in node.body.lines() {
+        writeln!(f, "{}", line)?;
+    }
+    for choice in &node.choices {
+        write_choice(f, choice, &target(&choice.target_node))?;
+    }
+    if let Some(next) = &node.next {
+        writeln!(f, "@next: {}", target(next))?;
+    }
+    Ok(())
+}
+
+/// Writes a choice line, falling back to the parsed condition when there is
+/// no condition text.
+fn write_choice(f: &mut fmt::Formatter<'_>, choice: &Choice, target: &str) -> fmt::Result {
+    write!(f, "* {} => {}", choice.text, target)?;
+    match (&choice.condition, &choice.condition_expr) {
+        (Some(condition), _) => write!(f, " @if {}", condition)?,
+        (None, Some(expr)) => write!(f, " @if {}", expr)?,
+        (None, None) => {}
+    }
+    writeln!(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, parse_expr, parse_project_with_loader, Dialogue, ParseOptions};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_print_canonical_form() {
+        let script = r#"
+@start: intro
+
+::intro
+Hello.
+@action: set gold = 1
+#greeting   #npc
+@who: Guard
+  Stop right there!
+@if gold > 0
+* Pay => pass
+* Run => flee @if not caught
+@mood: angry
+
+::pass
+@next: intro
+"#;
+        let dialogue = parse(script).unwrap();
+        let expected = "\
+@start: intro
+
+::intro
+@mood: angry
+@who: Guard
+@action: set gold = 1
+#greeting #npc
+Hello.
+  Stop right there!
+* Pay => pass @if gold > 0
+* Run => flee @if not caught
+
+::pass
+@next: intro
+";
+        assert_eq!(dialogue.to_string(), expected);
+        assert_eq!(parse(expected).unwrap(), dialogue);
+    }
+
+    #[test]
+    fn test_print_round_trips_examples() {
+        for path in [
+            "examples/varion_examples.va",
+            "examples/varion_long_example.vion",
+        ] {
+            let dialogue = parse(&fs::read_to_string(path).unwrap()).unwrap();
+            let printed = dialogue.to_string();
+            let reparsed = parse(&printed).unwrap();
+            assert_eq!(reparsed, dialogue, "{}", path);
+            assert_eq!(reparsed.to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn test_print_mixed_namespaces() {
+        let mut files: HashMap<PathBuf, String> = [
+            (
+                "main.va",
+                "@namespace village\n@start: start\n@include \"town.va\"\n@include \"lost.va\"\n\n::start\n* Travel => town.start\n* Rest => gate\n",
+            ),
+            ("town.va", "@namespace town\n\n::start\n@next: back\n\n::back\n* Home => village.start\n"),
+            ("lost.va", "::gate\n* Back => start\n"),
+        ]
+        .into_iter()
+        .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+        .collect();
+        let dialogue =
+            parse_project_with_loader("main.va", &mut files, &ParseOptions::default()).dialogue;
+        let expected = "\
+@start: village.start
+
+::town.start
+@next: town.back
+
+::town.back
+* Home => village.start
+
+::gate
+* Back => start
+
+::village.start
+* Travel => town.start
+* Rest => gate
+";
+        assert_eq!(dialogue.to_string(), expected);
+
+        let reparsed = parse(expected).unwrap();
+        assert_eq!(reparsed.order(), dialogue.order());
+        assert_eq!(
+            reparsed.entry_node().unwrap().name,
+            dialogue.entry_node().unwrap().name
+        );
+        let targets = |dialogue: &Dialogue| -> Vec<Option<String>> {
+            dialogue
+                .iter()
+                .flat_map(|node| {
+                    let targets = node.choices.iter().map(|choice| &choice.target_node);
+                    targets.chain(&node.next).map(move |target| {
+                        let resolved = dialogue.resolve_from(node, target);
+                        resolved.map(|node| node.name.clone())
+                    })
+                })
+                .collect()
+        };
+        assert_eq!(targets(&reparsed), targets(&dialogue));
+    }
+
+    #[test]
+    fn test_print_condition_without_text() {
+        let mut dialogue = parse("::start\n* Pay => shop @if gold >= 1\n").unwrap();
+        let choice = &mut dialogue.get_mut("start").unwrap().choices[0];
+        choice.condition = None;
+        choice.condition_expr = Some(parse_expr("(gold) >= 2").unwrap());
+        assert_eq!(
+            dialogue.to_string(),
+            "::start\n* Pay => shop @if gold >= 2\n"
+        );
+    }
+}
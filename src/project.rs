@@ -0,0 +1,518 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{parse_with_options, Dialogue, DuplicateNodePolicy, ParseError, ParseOptions, Span};
+
+/// An `@include "path"` directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Include {
+    /// The path as written, relative to the including script.
+    pub path: String,
+    /// The whole directive line.
+    pub span: Span,
+}
+
+/// Reads the scripts of a project, so that projects can be loaded from
+/// archives, embedded assets or memory as well as from disk.
+pub trait SourceLoader {
+    /// Returns the script at `path`.
+    fn load(&mut self, path: &Path) -> io::Result<String>;
+}
+
+/// Loads scripts from the file system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileLoader;
+
+impl SourceLoader for FileLoader {
+    fn load(&mut self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+/// Serves scripts from memory. Keys are compared after the same
+/// normalization as include paths, so `a/../b.va` finds `b.va`.
+impl SourceLoader for HashMap<PathBuf, String> {
+    fn load(&mut self, path: &Path) -> io::Result<String> {
+        self.get(path)
+            .or_else(|| {
+                self.iter()
+                    .find(|(key, _)| normalize(key) == path)
+                    .map(|(_, source)| source)
+            })
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such script"))
+    }
+}
+
+/// An error found while loading a project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProjectError {
+    /// A script could not be read.
+    Load {
+        path: PathBuf,
+        message: String,
+        /// The script and directive that included it; `None` for the root.
+        included_from: Option<(PathBuf, Span)>,
+    },
+    /// An error in one of the scripts.
+    Parse { file: PathBuf, error: ParseError },
+    /// An `@include` of a script that is already being included.
+    IncludeCycle {
+        file: PathBuf,
+        span: Span,
+        /// The scripts in the cycle, starting and ending with the included
+        /// one.
+        cycle: Vec<PathBuf>,
+    },
+    /// A node declared in two different scripts.
+    DuplicateNode {
+        name: String,
+        file: PathBuf,
+        /// The header of the later declaration.
+        span: Span,
+        first_file: PathBuf,
+        /// The header of the earlier declaration.
+        first: Span,
+    },
+}
+
+impl ProjectError {
+    /// Returns the script the error was found in.
+    pub fn file(&self) -> &Path {
+        match self {
+            ProjectError::Load {
+                included_from: Some((file, _)),
+                ..
+            } => file,
+            ProjectError::Load { path, .. } => path,
+            ProjectError::Parse { file, .. }
+            | ProjectError::IncludeCycle { file, .. }
+            | ProjectError::DuplicateNode { file, .. } => file,
+        }
+    }
+
+    /// Returns the location of the error in `file()`, if it has one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ProjectError::Load { included_from, .. } => included_from.as_ref().map(|(_, s)| *s),
+            ProjectError::Parse { error, .. } => Some(error.span()),
+            ProjectError::IncludeCycle { span, .. } | ProjectError::DuplicateNode { span, .. } => {
+                Some(*span)
+            }
+        }
+    }
+
+    /// Returns the error message without location information.
+    pub fn message(&self) -> String {
+        match self {
+            ProjectError::Load { path, message, .. } => {
+                format!("Cannot read '{}': {}.", path.display(), message)
+            }
+            ProjectError::Parse { error, .. } => error.message(),
+            ProjectError::IncludeCycle { cycle, .. } => {
+                let cycle: Vec<String> = cycle.iter().map(|p| p.display().to_string()).collect();
+                format!("Include cycle: {}.", cycle.join(" -> "))
+            }
+            ProjectError::DuplicateNode {
+                name,
+                first_file,
+                first,
+                ..
+            } => format!(
+                "Node '{}' is already declared in '{}' on line {}.",
+                name,
+                first_file.display(),
+                first.line
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span() {
+            Some(span) => write!(
+                f,
+                "{}: Error on line {}, column {}: {}",
+                self.file().display(),
+                span.line,
+                span.column,
+                self.message()
+            ),
+            None => write!(f, "{}: {}", self.file().display(), self.message()),
+        }
+    }
+}
+
+impl Error for ProjectError {}
+
+/// The result of loading a project in recovering mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectOutput {
+    /// Every node that could be parsed, from every script.
+    pub dialogue: Dialogue,
+    /// The scripts that were read, in the order their nodes were merged.
+    pub files: Vec<PathBuf>,
+    /// The script `dialogue.start` was declared in.
+    pub start_file: Option<PathBuf>,
+    /// Every error found.
+    pub errors: Vec<ProjectError>,
+}
+
+impl ProjectOutput {
+    /// Returns `true` if any error was found.
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// Returns the dialogue, or every error if there were any.
+    pub fn into_result(self) -> Result<Dialogue, Vec<ProjectError>> {
+        if self.errors.is_empty() {
+            Ok(self.dialogue)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Parses the script at `root` and every script it includes, reading them
+/// from disk.
+///
+/// Returns every error found in any of the scripts.
+pub fn parse_project(root: impl AsRef<Path>) -> Result<Dialogue, Vec<ProjectError>> {
+    parse_project_with_loader(root, &mut FileLoader, &ParseOptions::default()).into_result()
+}
+
+/// Parses a project in recovering mode, reading scripts through `loader`.
+///
+/// `@include "path"` lines before the first node pull in other scripts,
+/// resolved relative to the including one. An include works as if the
+/// included script were pasted in its place: its nodes come before those
+/// of the including script. A project has at most one `@start:`; the
+/// including script's takes precedence over those of the scripts it
+/// includes, which are reported as duplicates. A script included more than
+/// once is read once.
+///
+/// Each node's `file` is set to the script it was declared in, and its
+/// spans point into that script. A node declared in two scripts is reported
+/// with both locations, unless `options.duplicate_nodes` lets the later
+/// declaration win. `options.file` is ignored.
+pub fn parse_project_with_loader(
+    root: impl AsRef<Path>,
+    loader: &mut dyn SourceLoader,
+    options: &ParseOptions,
+) -> ProjectOutput {
+    let mut project = Project {
+        loader,
+        options,
+        output: ProjectOutput {
+            dialogue: Dialogue::new(),
+            files: Vec::new(),
+            start_file: None,
+            errors: Vec::new(),
+        },
+        stack: Vec::new(),
+        seen: HashSet::new(),
+    };
+    project.visit(normalize(root.as_ref()), None);
+    project.output
+}
+
+struct Project<'a> {
+    loader: &'a mut dyn SourceLoader,
+    options: &'a ParseOptions,
+    output: ProjectOutput,
+    /// The scripts currently being included, outermost first.
+    stack: Vec<PathBuf>,
+    seen: HashSet<PathBuf>,
+}
+
+impl Project<'_> {
+    fn visit(&mut self, path: PathBuf, included_from: Option<(PathBuf, Span)>) {
+        if let Some(index) = self.stack.iter().position(|file| *file == path) {
+            let (file, span) = included_from.expect("the root is never on the stack twice");
+            let mut cycle = self.stack[index..].to_vec();
+            cycle.push(path);
+            self.output
+                .errors
+                .push(ProjectError::IncludeCycle { file, span, cycle });
+            return;
+        }
+        if !self.seen.insert(path.clone()) {
+            return;
+        }
+
+        let source = match self.loader.load(&path) {
+            Ok(source) => source,
+            Err(error) => {
+                self.output.errors.push(ProjectError::Load {
+                    path,
+                    message: error.to_string(),
+                    included_from,
+                });
+                return;
+            }
+        };
+        let options = ParseOptions {
+            file: Some(path.display().to_string()),
+            ..self.options.clone()
+        };
+        let mut parsed = parse_with_options(&source, &options);
+        let errors = parsed.errors.into_iter().map(|error| ProjectError::Parse {
+            file: path.clone(),
+            error,
+        });
+        self.output.errors.extend(errors);
+        // Before the includes, so that the including script's `@start:` wins.
+        self.merge_start(&path, &mut parsed.dialogue);
+
+        self.stack.push(path.clone());
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        for include in &parsed.includes {
+            let target = normalize(&dir.join(&include.path));
+            self.visit(target, Some((path.clone(), include.span)));
+        }
+        self.stack.pop();
+
+        self.merge(&path, parsed.dialogue);
+        self.output.files.push(path);
+    }
+
+    /// Takes the `@start:` of a script, unless a script read before it
+    /// already has one.
+    fn merge_start(&mut self, path: &Path, dialogue: &mut Dialogue) {
+        let merged = &mut self.output.dialogue;
+        if let (Some(start), Some(span)) = (dialogue.start.take(), dialogue.start_span) {
+            if merged.start.is_some() {
+                self.output.errors.push(ProjectError::Parse {
+                    file: path.to_path_buf(),
+                    error: ParseError::DuplicateStart { span },
+                });
+            } else {
+                merged.start = Some(start);
+                merged.start_span = Some(span);
+                merged.start_namespace = dialogue.start_namespace.take();
+                self.output.start_file = Some(path.to_path_buf());
+            }
+        }
+    }
+
+    fn merge(&mut self, path: &Path, dialogue: Dialogue) {
+        let merged = &mut self.output.dialogue;
+        let Dialogue {
+            mut nodes, order, ..
+        } = dialogue;
+        for name in order {
+            let Some(node) = nodes.remove(&name) else {
+                continue;
+            };
+            if let Some(existing) = merged.get(&name) {
+                if self.options.duplicate_nodes == DuplicateNodePolicy::Error {
+                    self.output.errors.push(ProjectError::DuplicateNode {
+                        name,
+                        file: path.to_path_buf(),
+                        span: node.spans.header,
+                        first_file: PathBuf::from(existing.file.clone().unwrap_or_default()),
+                        first: existing.spans.header,
+                    });
+                    continue;
+                }
+            }
+            merged.insert_node(node);
+        }
+    }
+}
+
+/// Resolves `.` and `..` in `path` without touching the file system, so
+/// that one script reached through different paths is only read once.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) {
+                    normalized.pop();
+                } else {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(files: &[(&str, &str)]) -> HashMap<PathBuf, String> {
+        files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect()
+    }
+
+    fn load(files: &[(&str, &str)], root: &str) -> ProjectOutput {
+        parse_project_with_loader(root, &mut project(files), &ParseOptions::default())
+    }
+
+    #[test]
+    fn test_parse_project() {
+        let output = load(
+            &[
+                (
+                    "game/main.va",
+                    "@start: intro\n@include \"scenes/intro.va\"\n@include \"scenes/shop.va\"\n\n::hub\n* Shop => shop\n",
+                ),
+                (
+                    "game/scenes/intro.va",
+                    "@include \"../common.va\"\n\n::intro\n* Continue => hub\n",
+                ),
+                (
+                    "game/scenes/shop.va",
+                    "@include \"./../common.va\"\n\n::shop\n@next: bye\n",
+                ),
+                ("game/common.va", "::bye\n#end\n"),
+            ],
+            "game/main.va",
+        );
+        assert_eq!(output.errors, Vec::new());
+        assert_eq!(
+            output.files,
+            [
+                "game/common.va",
+                "game/scenes/intro.va",
+                "game/scenes/shop.va",
+                "game/main.va"
+            ]
+            .map(PathBuf::from)
+        );
+
+        let dialogue = output.dialogue;
+        assert_eq!(dialogue.order, vec!["bye", "intro", "shop", "hub"]);
+        assert_eq!(output.start_file, Some(PathBuf::from("game/main.va")));
+        assert_eq!(dialogue.start.as_deref(), Some("intro"));
+        assert_eq!(dialogue.entry_node().unwrap().name, "intro");
+        assert_eq!(
+            dialogue["shop"].file.as_deref(),
+            Some("game/scenes/shop.va")
+        );
+        assert_eq!(dialogue["shop"].spans.header.line, 3);
+        assert!(dialogue.validate().is_ok());
+    }
+
+    #[test]
+    fn test_project_errors() {
+        let files = [
+            (
+                "a.va",
+                "@include \"b.va\"\n@include \"missing.va\"\n::shared\n",
+            ),
+            ("b.va", "@start: shared\n@include \"c.va\"\n::b\n"),
+            (
+                "c.va",
+                "@start: b\n@include \"a.va\"\n::shared\n::c\n* broken\n",
+            ),
+        ];
+        let output = load(&files, "a.va");
+        assert_eq!(output.start_file, Some(PathBuf::from("b.va")));
+        let errors = output.errors;
+        assert_eq!(errors.len(), 5, "{:#?}", errors);
+
+        assert!(matches!(
+            &errors[0],
+            ProjectError::Parse { file, error: ParseError::InvalidChoiceFormat { .. } }
+                if file == Path::new("c.va")
+        ));
+        assert_eq!(
+            errors[1],
+            ProjectError::Parse {
+                file: PathBuf::from("c.va"),
+                error: ParseError::DuplicateStart {
+                    span: parse_with_options(files[2].1, &ParseOptions::default())
+                        .dialogue
+                        .start_span
+                        .unwrap()
+                },
+            }
+        );
+        assert_eq!(
+            errors[2].to_string(),
+            "c.va: Error on line 2, column 1: Include cycle: a.va -> b.va -> c.va -> a.va."
+        );
+        assert_eq!(
+            errors[3].to_string(),
+            "a.va: Error on line 2, column 1: Cannot read 'missing.va': no such script."
+        );
+        let ProjectError::DuplicateNode {
+            name,
+            file,
+            span,
+            first_file,
+            first,
+        } = &errors[4]
+        else {
+            panic!("expected a duplicate node, got {:?}", errors[4]);
+        };
+        assert_eq!(name, "shared");
+        assert_eq!((file.to_str(), span.line), (Some("a.va"), 3));
+        assert_eq!((first_file.to_str(), first.line), (Some("c.va"), 3));
+
+        let options = ParseOptions {
+            duplicate_nodes: DuplicateNodePolicy::Override,
+            ..ParseOptions::default()
+        };
+        let output = parse_project_with_loader("a.va", &mut project(&files), &options);
+        assert_eq!(output.errors.len(), 4);
+        assert_eq!(output.dialogue["shared"].file.as_deref(), Some("a.va"));
+
+        let output = load(
+            &[
+                ("main.va", "@include \"b.va\"\n@start: a\n::a\n"),
+                ("b.va", "@start: b\n::b\n"),
+            ],
+            "main.va",
+        );
+        assert_eq!(output.dialogue.start.as_deref(), Some("a"));
+        assert_eq!(output.start_file, Some(PathBuf::from("main.va")));
+        assert!(matches!(
+            &output.errors[..],
+            [ProjectError::Parse { file, error: ParseError::DuplicateStart { .. } }]
+                if file == Path::new("b.va")
+        ));
+
+        assert!(matches!(
+            parse_project("does/not/exist.va").unwrap_err()[..],
+            [ProjectError::Load {
+                included_from: None,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_include_syntax() {
+        let output = crate::parse_with_diagnostics(
+            "@include \"a.va\"\n@include  \"b c.va\" \n@include nope.va\n::n\n@include \"d.va\"\n",
+        );
+        let paths: Vec<_> = output.includes.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.va", "b c.va"]);
+        assert!(matches!(
+            output.errors[..],
+            [
+                ParseError::InvalidInclude { .. },
+                ParseError::IncludeInsideNode { .. }
+            ]
+        ));
+        assert!(output.dialogue.contains("n"));
+    }
+}
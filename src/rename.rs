@@ -0,0 +1,513 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::{parse_with_options, Dialogue, Node, ParseError, ParseOptions, Span};
+
+/// A replacement of the text at `span`, which editors can apply directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// The file to edit, for edits produced from several files.
+    pub file: Option<String>,
+    pub span: Span,
+    pub new_text: String,
+}
+
+/// An error that prevents a rename.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenameError {
+    /// A script could not be parsed, so its references cannot be found
+    /// reliably.
+    Parse {
+        file: Option<String>,
+        error: ParseError,
+    },
+    /// No node has the old name.
+    NodeNotFound { name: String },
+    /// A node with the new name already exists.
+    NameTaken {
+        name: String,
+        file: Option<String>,
+        span: Span,
+    },
+    /// The new name cannot be written as a node name, or is outside the
+    /// namespace of the node being renamed.
+    InvalidName { name: String },
+    /// Two scripts declare the same node, so references to it are
+    /// ambiguous.
+    DuplicateNode {
+        name: String,
+        /// The file of the earlier declaration.
+        first_file: Option<String>,
+        file: Option<String>,
+        /// The header of the later declaration.
+        span: Span,
+    },
+    /// After the rename, a reference would resolve to a different node,
+    /// because the new name shadows another node or is captured by
+    /// another namespace.
+    ReferenceChanged {
+        file: Option<String>,
+        /// The reference.
+        span: Span,
+        /// The node it resolves to before the rename, or the target as
+        /// written if it does not resolve.
+        before: String,
+        /// The node it would resolve to after the rename.
+        after: String,
+    },
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameError::Parse {
+                file: Some(file),
+                error,
+            } => write!(f, "{}: {}", file, error),
+            RenameError::Parse { file: None, error } => write!(f, "{}", error),
+            RenameError::NodeNotFound { name } => write!(f, "Node '{}' does not exist.", name),
+            RenameError::NameTaken { name, span, .. } => {
+                write!(f, "Node '{}' already exists on line {}.", name, span.line)
+            }
+            RenameError::InvalidName { name } => {
+                write!(f, "'{}' is not a valid node name.", name)
+            }
+            RenameError::DuplicateNode {
+                name,
+                first_file,
+                file,
+                ..
+            } => write!(
+                f,
+                "Node '{}' is declared in both '{}' and '{}'.",
+                name,
+                first_file.as_deref().unwrap_or_default(),
+                file.as_deref().unwrap_or_default()
+            ),
+            RenameError::ReferenceChanged {
+                span,
+                before,
+                after,
+                ..
+            } => write!(
+                f,
+                "The reference to '{}' on line {} would resolve to '{}' instead.",
+                before, span.line, after
+            ),
+        }
+    }
+}
+
+impl Error for RenameError {}
+
+/// Renames the node `old` to `new` in a single script, returning edits for
+/// its declaration and every `@start:`, `@next:` and choice target that
+/// refers to it.
+///
+/// Names are full names, so `village.start` for `::start` under
+/// `@namespace village`. A node keeps its namespace, so `new` must start
+/// with the same one; the declaration and references written inside the
+/// namespace get the new local name.
+///
+/// Fails if the script does not parse, `old` does not exist, `new` is
+/// already taken or cannot be written as a node name in `old`'s namespace,
+/// or the rename would change which node another reference resolves to.
+pub fn rename_node(source: &str, old: &str, new: &str) -> Result<Vec<TextEdit>, RenameError> {
+    rename(&[(None, source)], old, new)
+}
+
+/// Renames a node across several scripts, given as `(file, source)` pairs.
+/// Each edit names the file it applies to.
+///
+/// Also fails if two scripts declare a node with the same name.
+pub fn rename_node_in_files(
+    files: &[(&str, &str)],
+    old: &str,
+    new: &str,
+) -> Result<Vec<TextEdit>, RenameError> {
+    let files: Vec<_> = files
+        .iter()
+        .map(|(file, source)| (Some(file.to_string()), *source))
+        .collect();
+    rename(&files, old, new)
+}
+
+fn rename(
+    files: &[(Option<String>, &str)],
+    old: &str,
+    new: &str,
+) -> Result<Vec<TextEdit>, RenameError> {
+    let (dialogues, project) = load(files)?;
+    let Some(node) = project.get(old) else {
+        return Err(RenameError::NodeNotFound {
+            name: old.to_string(),
+        });
+    };
+    // A node keeps its namespace; only the local name after it changes.
+    let local = match &node.namespace {
+        Some(namespace) => new
+            .strip_prefix(namespace.as_str())
+            .and_then(|name| name.strip_prefix('.')),
+        None => Some(new),
+    };
+    let Some(local) = local.filter(|local| is_valid_name(local)) else {
+        return Err(RenameError::InvalidName {
+            name: new.to_string(),
+        });
+    };
+    if old == new {
+        return Ok(Vec::new());
+    }
+    if let Some(node) = project.get(new) {
+        return Err(RenameError::NameTaken {
+            name: new.to_string(),
+            file: node.file.clone(),
+            span: node.spans.name,
+        });
+    }
+
+    let mut edits = Vec::new();
+    for ((file, source), dialogue) in files.iter().zip(&dialogues) {
+        // References written with the full name keep it; the rest are
+        // written inside the namespace and get the local name.
+        let mut renamed: Vec<(Span, &str)> = resolved_references(dialogue, &project)
+            .into_iter()
+            .filter(|(target, _)| *target == old)
+            .map(|(_, span)| {
+                if &source[span.start..span.end] == old {
+                    (span, new)
+                } else {
+                    (span, local)
+                }
+            })
+            .collect();
+        if let Some(node) = dialogue.get(old) {
+            renamed.push((node.spans.name, local));
+        }
+        renamed.sort_by_key(|(span, _)| span.start);
+        edits.extend(renamed.into_iter().map(|(span, new_text)| TextEdit {
+            file: file.clone(),
+            span,
+            new_text: new_text.to_string(),
+        }));
+    }
+
+    // The new name can shadow another node for references written inside
+    // its namespace, and a full name written inside another namespace can
+    // be captured there, so check every reference against the result.
+    let renamed_files: Vec<(Option<String>, String)> = files
+        .iter()
+        .map(|(file, source)| {
+            let edits: Vec<TextEdit> = edits
+                .iter()
+                .filter(|edit| edit.file == *file)
+                .cloned()
+                .collect();
+            (file.clone(), apply_edits(source, &edits))
+        })
+        .collect();
+    let renamed_files: Vec<(Option<String>, &str)> = renamed_files
+        .iter()
+        .map(|(file, source)| (file.clone(), source.as_str()))
+        .collect();
+    let (renamed, renamed_project) = load(&renamed_files)?;
+    for (((file, _), dialogue), renamed) in files.iter().zip(&dialogues).zip(&renamed) {
+        let before = resolved_references(dialogue, &project);
+        let after = resolved_references(renamed, &renamed_project);
+        for ((before, span), (after, _)) in before.into_iter().zip(after) {
+            let expected = if before == old { new } else { before };
+            if after != expected {
+                return Err(RenameError::ReferenceChanged {
+                    file: file.clone(),
+                    span,
+                    before: before.to_string(),
+                    after: after.to_string(),
+                });
+            }
+        }
+    }
+    Ok(edits)
+}
+
+/// Parses every file and merges their nodes into one project. References
+/// are resolved against the project, as in one loaded with
+/// `parse_project`, so `@next: hub` in one file finds `town.hub` declared
+/// in another.
+pub(crate) fn load(
+    files: &[(Option<String>, &str)],
+) -> Result<(Vec<Dialogue>, Dialogue), RenameError> {
+    let mut dialogues = Vec::new();
+    for (file, source) in files {
+        let options = ParseOptions {
+            file: file.clone(),
+            ..ParseOptions::default()
+        };
+        let dialogue = parse_with_options(source, &options)
+            .into_result()
+            .map_err(|error| RenameError::Parse {
+                file: file.clone(),
+                error,
+            })?;
+        dialogues.push(dialogue);
+    }
+
+    let mut project = Dialogue::new();
+    for node in dialogues.iter().flat_map(Dialogue::iter) {
+        if let Some(first) = project.get(&node.name) {
+            return Err(RenameError::DuplicateNode {
+                name: node.name.clone(),
+                first_file: first.file.clone(),
+                file: node.file.clone(),
+                span: node.spans.name,
+            });
+        }
+        project.insert_node(node.clone());
+    }
+    Ok((dialogues, project))
+}
+
+/// Applies edits for a single script. Edits must not overlap; their `file`
+/// is ignored.
+pub fn apply_edits(source: &str, edits: &[TextEdit]) -> String {
+    let mut edits: Vec<&TextEdit> = edits.iter().collect();
+    edits.sort_by_key(|edit| edit.span.start);
+    let mut out = String::with_capacity(source.len());
+    let mut last = 0;
+    for edit in edits {
+        out.push_str(&source[last..edit.span.start]);
+        out.push_str(&edit.new_text);
+        last = edit.span.end;
+    }
+    out.push_str(&source[last..]);
+    out
+}
+
+/// Every place a node name is referenced in `dialogue`: `@start:`,
+/// `@next:` and choice targets, with the span of the name.
+///
+/// Each reference carries the full name of the node it resolves to among
+/// the nodes of `project`, which may hold nodes from other files, or the
+/// target as written if it does not resolve.
+pub(crate) fn resolved_references<'a>(
+    dialogue: &'a Dialogue,
+    project: &'a Dialogue,
+) -> Vec<(&'a str, Span)> {
+    let mut references = Vec::new();
+    if let (Some(start), Some(span)) = (&dialogue.start, dialogue.start_span) {
+        let namespace = dialogue.start_namespace.as_deref();
+        references.push((resolved(project, namespace, start), span));
+    }
+    for node in dialogue.iter() {
+        references.extend(node_references(node, project));
+    }
+    references
+}
+
+/// The `@next:` and choice target references in `node`, resolved among the
+/// nodes of `project`.
+pub(crate) fn node_references<'a>(node: &'a Node, project: &'a Dialogue) -> Vec<(&'a str, Span)> {
+    let namespace = node.namespace.as_deref();
+    let mut references = Vec::new();
+    if let (Some(next), Some(span)) = (&node.next, node.spans.next) {
+        references.push((resolved(project, namespace, next), span));
+    }
+    for choice in &node.choices {
+        references.push((
+            resolved(project, namespace, &choice.target_node),
+            choice.target_span,
+        ));
+    }
+    references
+}
+
+/// The full name `target` resolves to, or `target` if it does not.
+pub(crate) fn resolved<'a>(
+    project: &'a Dialogue,
+    namespace: Option<&str>,
+    target: &'a str,
+) -> &'a str {
+    project
+        .resolve(namespace, target)
+        .map_or(target, |node| node.name.as_str())
+}
+
+/// Checks that `name` reads back unchanged from `::name`, `@next: name` and
+/// `* text => name`.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.trim() == name
+        && !name.contains(['\n', '\r'])
+        && !name.contains("=>")
+        && !name.contains("@if")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    const SCRIPT: &str = "\
+@start: ask_help
+
+::ask_help
+// ask_help in a comment stays
+Help me!
+* Again => ask_help @if tries < 3
+* Stop => done
+
+::done
+@next:  ask_help
+";
+
+    #[test]
+    fn test_rename_node() {
+        let edits = rename_node(SCRIPT, "ask_help", "plead").unwrap();
+        assert_eq!(edits.len(), 4);
+        assert_eq!((edits[1].span.line, edits[1].span.column), (3, 3));
+        assert!(edits.iter().all(|edit| edit.file.is_none()));
+
+        let renamed = apply_edits(SCRIPT, &edits);
+        assert_eq!(
+            renamed,
+            SCRIPT
+                .replace("@start: ask_help", "@start: plead")
+                .replace("::ask_help", "::plead")
+                .replace("=> ask_help", "=> plead")
+                .replace("@next:  ask_help", "@next:  plead")
+        );
+        let dialogue = parse(&renamed).unwrap();
+        assert!(dialogue.validate().is_ok());
+        assert_eq!(dialogue.start.as_deref(), Some("plead"));
+    }
+
+    #[test]
+    fn test_rename_refusals() {
+        assert_eq!(
+            rename_node(SCRIPT, "ask_help", "done"),
+            Err(RenameError::NameTaken {
+                name: "done".to_string(),
+                file: None,
+                span: parse(SCRIPT).unwrap()["done"].spans.name,
+            })
+        );
+        assert_eq!(
+            rename_node(SCRIPT, "missing", "x").unwrap_err().to_string(),
+            "Node 'missing' does not exist."
+        );
+        for name in ["", " padded", "a => b", "two\nlines", "x @if y"] {
+            assert!(matches!(
+                rename_node(SCRIPT, "done", name),
+                Err(RenameError::InvalidName { .. })
+            ));
+        }
+        assert!(matches!(
+            rename_node("::a\n* broken\n", "a", "b"),
+            Err(RenameError::Parse { file: None, .. })
+        ));
+        assert_eq!(rename_node(SCRIPT, "done", "done"), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_rename_in_namespace() {
+        let files = [
+            (
+                "village.va",
+                "@namespace village\n@start: start\n\n::start\n* Stay => start\n* Go => town.square\n",
+            ),
+            (
+                "town.va",
+                "@namespace town\n\n::square\n* Back => village.start\n",
+            ),
+        ];
+        let edits = rename_node_in_files(&files, "village.start", "village.gate").unwrap();
+        let texts: Vec<_> = edits
+            .iter()
+            .map(|edit| (edit.file.as_deref().unwrap(), edit.new_text.as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                ("village.va", "gate"),
+                ("village.va", "gate"),
+                ("village.va", "gate"),
+                ("town.va", "village.gate"),
+            ]
+        );
+
+        assert!(matches!(
+            rename_node_in_files(&files, "village.start", "gate"),
+            Err(RenameError::InvalidName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rename_in_namespace_across_files() {
+        let files = [
+            ("a.va", "@namespace town\n\n::hub\n* Shop => shop\n"),
+            ("b.va", "@namespace town\n\n::shop\n@next: hub\n"),
+        ];
+        let edits = rename_node_in_files(&files, "town.hub", "town.square").unwrap();
+        let texts: Vec<_> = edits
+            .iter()
+            .map(|edit| (edit.file.as_deref().unwrap(), edit.new_text.as_str()))
+            .collect();
+        assert_eq!(texts, vec![("a.va", "square"), ("b.va", "square")]);
+        assert_eq!(
+            apply_edits(files[1].1, &edits[1..]),
+            "@namespace town\n\n::shop\n@next: square\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_refuses_shadowing() {
+        let files = [
+            ("g.va", "::gate\n"),
+            ("v.va", "@namespace village\n\n::start\n* Go => gate\n"),
+        ];
+        let err = rename_node_in_files(&files, "village.start", "village.gate").unwrap_err();
+        assert_eq!(
+            err,
+            RenameError::ReferenceChanged {
+                file: Some("v.va".to_string()),
+                span: parse(files[1].1).unwrap()["village.start"].choices[0].target_span,
+                before: "gate".to_string(),
+                after: "village.gate".to_string(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "The reference to 'gate' on line 4 would resolve to 'village.gate' instead."
+        );
+
+        // Any other new name leaves `=> gate` on the global node.
+        let edits = rename_node_in_files(&files, "village.start", "village.porch").unwrap();
+        assert_eq!(edits.len(), 1);
+    }
+
+    #[test]
+    fn test_rename_across_files() {
+        let files = [
+            ("intro.va", "::intro\n* Shop => shop\n"),
+            ("shop.va", "::shop\nWelcome.\n@next: intro\n"),
+            ("other.va", "::other\n"),
+        ];
+        let edits = rename_node_in_files(&files, "shop", "store").unwrap();
+        let touched: Vec<_> = edits.iter().map(|edit| edit.file.as_deref()).collect();
+        assert_eq!(touched, vec![Some("intro.va"), Some("shop.va")]);
+
+        let err = rename_node_in_files(&files, "intro", "other").unwrap_err();
+        assert!(
+            matches!(err, RenameError::NameTaken { file: Some(ref file), .. } if file == "other.va")
+        );
+
+        let files = [("a.va", "::hub\n"), ("b.va", "::hub\n")];
+        let err = rename_node_in_files(&files, "hub", "square").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Node 'hub' is declared in both 'a.va' and 'b.va'."
+        );
+        assert!(
+            matches!(err, RenameError::DuplicateNode { file: Some(ref file), .. } if file == "b.va")
+        );
+    }
+}
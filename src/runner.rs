@@ -0,0 +1,732 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+use crate::eval::binary;
+use crate::state::{Phase, Rng};
+use crate::{
+    evaluate, render_text, Action, ActionKind, BinaryOp, Choice, Dialogue, DialogueState,
+    EvalError, Node, RenderContext, RenderOptions, Span, StateError, Value,
+};
+
+/// What the runner produced on a call to `DialogueRunner::advance`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step<'a> {
+    /// An action the runner does not execute itself (`call` and custom
+    /// commands), for the host engine to handle.
+    Action { node: &'a Node, action: &'a Action },
+    /// One non-empty line of a node's body, with `{expr}` substitutions
+    /// filled in.
+    Line { node: &'a Node, text: String },
+    /// The choices whose conditions currently hold. The runner waits for
+    /// `DialogueRunner::choose` before moving on.
+    Choices {
+        node: &'a Node,
+        choices: Vec<AvailableChoice<'a>>,
+    },
+    /// The conversation is over.
+    End,
+}
+
+/// A choice presented to the player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvailableChoice<'a> {
+    /// Index of the choice in `Node::choices`.
+    pub index: usize,
+    /// The choice text, with `{expr}` substitutions filled in.
+    pub text: String,
+    pub choice: &'a Choice,
+}
+
+/// An error produced while running a dialogue.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunError {
+    /// The dialogue has no nodes to start from.
+    NoEntryNode,
+    /// A node name that does not exist in the dialogue.
+    NodeNotFound {
+        name: String,
+        /// The node holding the broken reference, if any.
+        from: Option<String>,
+        /// The location of the broken reference.
+        span: Option<Span>,
+    },
+    /// A condition or action expression failed to evaluate.
+    Eval {
+        node: String,
+        span: Span,
+        error: EvalError,
+    },
+    /// `choose` was called while no choices were pending.
+    NotAwaitingChoice,
+    /// `choose` was called with an index past the presented choices.
+    InvalidChoice { index: usize, available: usize },
+    /// `@next` led back to `node` without producing a step, so `advance`
+    /// would never return.
+    NoProgress { node: String },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::NoEntryNode => write!(f, "The dialogue has no nodes."),
+            RunError::NodeNotFound {
+                name,
+                from: Some(from),
+                span: Some(span),
+            } => write!(
+                f,
+                "Node '{}' referenced from '{}' on line {} does not exist.",
+                name, from, span.line
+            ),
+            RunError::NodeNotFound { name, .. } => write!(f, "Node '{}' does not exist.", name),
+            RunError::Eval { node, span, error } => {
+                write!(
+                    f,
+                    "Error in node '{}' on line {}: {}.",
+                    node, span.line, error
+                )
+            }
+            RunError::NotAwaitingChoice => write!(f, "No choices are waiting for an answer."),
+            RunError::InvalidChoice { index, available } => write!(
+                f,
+                "Choice {} is out of range; {} choices are available.",
+                index, available
+            ),
+            RunError::NoProgress { node } => write!(
+                f,
+                "Node '{}' was reached again without producing any output.",
+                node
+            ),
+        }
+    }
+}
+
+impl Error for RunError {}
+
+/// Walks a `Dialogue` one step at a time.
+///
+/// Entering a node runs its actions in order: `set`, `add` and `unset` are
+/// applied to the runner's variables, while `call` and custom commands are
+/// handed to the host as `Step::Action`. The node's body lines follow, then
+/// either its available choices or, if it has none, its `@next` node. A node
+/// with neither ends the conversation, as does one whose choices are all
+/// unavailable.
+#[derive(Debug, Clone)]
+pub struct DialogueRunner<'a> {
+    dialogue: &'a Dialogue,
+    node: &'a Node,
+    state: DialogueState,
+    render: RenderOptions,
+}
+
+impl<'a> DialogueRunner<'a> {
+    /// Creates a runner positioned at the dialogue's entry node.
+    pub fn new(dialogue: &'a Dialogue) -> Result<Self, RunError> {
+        let node = match (&dialogue.start, dialogue.entry_node()) {
+            (_, Some(node)) => node,
+            (Some(start), None) => {
+                return Err(RunError::NodeNotFound {
+                    name: start.clone(),
+                    from: None,
+                    span: dialogue.start_span,
+                })
+            }
+            (None, None) => return Err(RunError::NoEntryNode),
+        };
+        Ok(Self::at(dialogue, node))
+    }
+
+    /// Creates a runner positioned at the named node.
+    pub fn start_at(dialogue: &'a Dialogue, name: &str) -> Result<Self, RunError> {
+        let node = dialogue.get(name).ok_or_else(|| RunError::NodeNotFound {
+            name: name.to_string(),
+            from: None,
+            span: None,
+        })?;
+        Ok(Self::at(dialogue, node))
+    }
+
+    fn at(dialogue: &'a Dialogue, node: &'a Node) -> Self {
+        DialogueRunner {
+            dialogue,
+            node,
+            state: DialogueState::new(&node.name, 0),
+            render: RenderOptions::default(),
+        }
+    }
+
+    /// Resumes a conversation from a saved state.
+    ///
+    /// Fails if the saved node no longer exists or no longer fits the saved
+    /// position, e.g. because the script changed since the state was saved.
+    pub fn restore(dialogue: &'a Dialogue, state: DialogueState) -> Result<Self, StateError> {
+        let node = dialogue
+            .get(&state.node)
+            .ok_or_else(|| StateError::NodeNotFound {
+                name: state.node.clone(),
+            })?;
+        let incompatible = |message: String| StateError::IncompatibleNode {
+            name: node.name.clone(),
+            message,
+        };
+        match &state.phase {
+            Phase::Actions { next } if *next > node.actions.len() => {
+                return Err(incompatible(format!(
+                    "action {} is past the node's {} actions",
+                    next,
+                    node.actions.len()
+                )));
+            }
+            Phase::AwaitingChoice(indices) => {
+                if let Some(index) = indices.iter().find(|&&i| i >= node.choices.len()) {
+                    return Err(incompatible(format!(
+                        "pending choice {} is past the node's {} choices",
+                        index,
+                        node.choices.len()
+                    )));
+                }
+            }
+            _ => {}
+        }
+        Ok(DialogueRunner {
+            dialogue,
+            node,
+            state,
+            render: RenderOptions::default(),
+        })
+    }
+
+    /// Restarts the random number generator from `seed`, which also picks
+    /// the options of `~shuffle` alternatives in text.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.state.seed = seed;
+        self.state.rng = Rng::new(seed);
+    }
+
+    /// Sets how `{expr}` substitutions in body and choice text are
+    /// rendered. By default unknown variables are left in the text.
+    pub fn set_render_options(&mut self, options: RenderOptions) {
+        self.render = options;
+    }
+
+    /// Returns the conversation state, e.g. to save it.
+    pub fn state(&self) -> &DialogueState {
+        &self.state
+    }
+
+    /// Consumes the runner, returning its state.
+    pub fn into_state(self) -> DialogueState {
+        self.state
+    }
+
+    /// Returns the node the runner is in.
+    pub fn current_node(&self) -> &'a Node {
+        self.node
+    }
+
+    pub fn variables(&self) -> &HashMap<String, Value> {
+        &self.state.variables
+    }
+
+    pub fn variables_mut(&mut self) -> &mut HashMap<String, Value> {
+        &mut self.state.variables
+    }
+
+    /// Returns how many times the named node has been entered.
+    pub fn visit_count(&self, name: &str) -> u32 {
+        self.state.visit_count(name)
+    }
+
+    /// Returns `true` once the conversation is over.
+    pub fn is_ended(&self) -> bool {
+        self.state.is_ended()
+    }
+
+    /// Produces the next step of the conversation.
+    ///
+    /// While choices are pending, this keeps returning them until
+    /// `choose` is called. Entering the same node twice in one call fails
+    /// with `RunError::NoProgress`, as the `@next` cycle would never yield.
+    pub fn advance(&mut self) -> Result<Step<'a>, RunError> {
+        let mut entered = HashSet::new();
+        loop {
+            match &mut self.state.phase {
+                Phase::Enter => {
+                    if !entered.insert(self.node.name.as_str()) {
+                        return Err(RunError::NoProgress {
+                            node: self.node.name.clone(),
+                        });
+                    }
+                    *self.state.visits.entry(self.node.name.clone()).or_insert(0) += 1;
+                    self.state.phase = Phase::Actions { next: 0 };
+                }
+                Phase::Actions { next } => {
+                    let next = *next;
+                    match self.node.actions.get(next) {
+                        Some(action) => {
+                            // A failed action stays current, so the next
+                            // call retries it rather than skipping it.
+                            let handled = self.execute(action)?;
+                            self.state.phase = Phase::Actions { next: next + 1 };
+                            if !handled {
+                                return Ok(Step::Action {
+                                    node: self.node,
+                                    action,
+                                });
+                            }
+                        }
+                        None => self.state.phase = Phase::Lines(self.body_lines()?),
+                    }
+                }
+                Phase::Lines(lines) => match lines.pop_front() {
+                    Some(text) => {
+                        return Ok(Step::Line {
+                            node: self.node,
+                            text,
+                        })
+                    }
+                    None => self.finish_node()?,
+                },
+                Phase::AwaitingChoice(indices) => {
+                    let indices = indices.clone();
+                    return Ok(Step::Choices {
+                        node: self.node,
+                        choices: self.present(&indices)?,
+                    });
+                }
+                Phase::Ended => return Ok(Step::End),
+            }
+        }
+    }
+
+    /// Picks the `index`-th of the choices last returned by `advance` and
+    /// moves to its target node.
+    pub fn choose(&mut self, index: usize) -> Result<(), RunError> {
+        let Phase::AwaitingChoice(indices) = &self.state.phase else {
+            return Err(RunError::NotAwaitingChoice);
+        };
+        let Some(&choice_index) = indices.get(index) else {
+            return Err(RunError::InvalidChoice {
+                index,
+                available: indices.len(),
+            });
+        };
+        let choice = &self.node.choices[choice_index];
+        self.enter(&choice.target_node, Some(choice.target_span))
+    }
+
+    /// Applies a built-in action, returning `false` for actions the host
+    /// must handle.
+    fn execute(&mut self, action: &Action) -> Result<bool, RunError> {
+        let eval_error = |error| RunError::Eval {
+            node: self.node.name.clone(),
+            span: action.span,
+            error,
+        };
+        let variables = &mut self.state.variables;
+        match &action.kind {
+            ActionKind::Set { variable, value } => {
+                let value = evaluate(value, variables).map_err(eval_error)?;
+                variables.insert(variable.clone(), value);
+            }
+            ActionKind::Add { variable, amount } => {
+                let amount = evaluate(amount, variables).map_err(eval_error)?;
+                let current = variables.get(variable).cloned().unwrap_or(Value::Int(0));
+                let value = binary(BinaryOp::Add, current, amount).map_err(eval_error)?;
+                variables.insert(variable.clone(), value);
+            }
+            ActionKind::Unset { variable } => {
+                variables.remove(variable);
+            }
+            ActionKind::Call { .. } | ActionKind::Custom { .. } => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// The visit of the current node, and a seed that differs between nodes
+    /// so their `~shuffle` alternatives do not pick in step.
+    fn render_context(&self) -> RenderContext {
+        // FNV-1a, which unlike `std`'s hashers is the same in every build.
+        let name_hash = self
+            .node
+            .name
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        RenderContext {
+            visit: self.visit_count(&self.node.name),
+            seed: self.state.seed ^ name_hash,
+        }
+    }
+
+    /// Renders the current node's body and splits it into its non-empty
+    /// lines.
+    fn body_lines(&self) -> Result<VecDeque<String>, RunError> {
+        let body = render_text(
+            &self.node.body_segments,
+            &self.state.variables,
+            &self.render_context(),
+            &self.render,
+        )
+        .map_err(|error| RunError::Eval {
+            node: self.node.name.clone(),
+            span: self.node.spans.body.unwrap_or(self.node.spans.header),
+            error,
+        })?;
+        Ok(body
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Renders the choices at `indices` for the player.
+    fn present(&self, indices: &[usize]) -> Result<Vec<AvailableChoice<'a>>, RunError> {
+        let node = self.node;
+        let context = self.render_context();
+        indices
+            .iter()
+            .map(|&index| {
+                let choice = &node.choices[index];
+                let text = render_text(
+                    &choice.text_segments,
+                    &self.state.variables,
+                    &context,
+                    &self.render,
+                )
+                .map_err(|error| RunError::Eval {
+                    node: node.name.clone(),
+                    span: choice.span,
+                    error,
+                })?;
+                Ok(AvailableChoice {
+                    index,
+                    text,
+                    choice,
+                })
+            })
+            .collect()
+    }
+
+    /// Moves past the current node once its lines are exhausted.
+    fn finish_node(&mut self) -> Result<(), RunError> {
+        if !self.node.choices.is_empty() {
+            let mut available = Vec::new();
+            for (index, choice) in self.node.choices.iter().enumerate() {
+                let shown = choice
+                    .is_available(&self.state.variables)
+                    .map_err(|error| RunError::Eval {
+                        node: self.node.name.clone(),
+                        span: choice.condition_span.unwrap_or(choice.span),
+                        error,
+                    })?;
+                if shown {
+                    available.push(index);
+                }
+            }
+            self.state.phase = if available.is_empty() {
+                Phase::Ended
+            } else {
+                Phase::AwaitingChoice(available)
+            };
+            return Ok(());
+        }
+        match &self.node.next {
+            Some(next) => self.enter(next, self.node.spans.next),
+            None => {
+                self.state.phase = Phase::Ended;
+                Ok(())
+            }
+        }
+    }
+
+    fn enter(&mut self, name: &str, span: Option<Span>) -> Result<(), RunError> {
+        let node =
+            self.dialogue
+                .resolve_from(self.node, name)
+                .ok_or_else(|| RunError::NodeNotFound {
+                    name: name.to_string(),
+                    from: Some(self.node.name.clone()),
+                    span,
+                })?;
+        self.node = node;
+        self.state.node = node.name.clone();
+        self.state.phase = Phase::Enter;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// Runs until choices or the end, collecting lines and host actions.
+    fn run_until_input(runner: &mut DialogueRunner) -> (Vec<String>, Vec<String>) {
+        let mut output = Vec::new();
+        loop {
+            match runner.advance().unwrap() {
+                Step::Line { text, .. } => output.push(text),
+                Step::Action { action, .. } => output.push(format!("[{}]", action.command)),
+                Step::Choices { choices, .. } => {
+                    return (output, choices.into_iter().map(|c| c.text).collect())
+                }
+                Step::End => return (output, Vec::new()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_runner_walks_dialogue() {
+        let script = r#"
+::start
+@action: set reputation = 1
+@action: play_sound hello.wav
+Welcome!
+  What can I do for you?
+* I need help! => ask_help
+* Rude reply => end @if reputation > 5
+
+::ask_help
+@action: reputation += 2
+Really?
+@next: end
+
+::end
+@action: call fade_out()
+Bye.
+"#;
+        let dialogue = parse(script).unwrap();
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        let (lines, choices) = run_until_input(&mut runner);
+        assert_eq!(
+            lines,
+            vec![
+                "[play_sound hello.wav]",
+                "Welcome!",
+                "What can I do for you?"
+            ]
+        );
+        assert_eq!(choices, vec!["I need help!"]);
+        assert_eq!(runner.advance().unwrap(), runner.clone().advance().unwrap());
+
+        runner.choose(0).unwrap();
+        let (lines, choices) = run_until_input(&mut runner);
+        assert_eq!(lines, vec!["Really?", "[call fade_out()]", "Bye."]);
+        assert!(choices.is_empty());
+        assert!(runner.is_ended());
+        assert_eq!(runner.advance().unwrap(), Step::End);
+        assert_eq!(runner.variables()["reputation"], Value::Int(3));
+        assert_eq!(runner.visit_count("ask_help"), 1);
+        assert_eq!(runner.current_node().name, "end");
+    }
+
+    #[test]
+    fn test_runner_renders_text() {
+        let script =
+            "::start\n@action: set gold = 5\nHello, {name}!\n{if paid}Thanks again!{else}Who are you?{end}\n* Pay {gold * 2} gold => start\n";
+        let dialogue = parse(script).unwrap();
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        runner
+            .variables_mut()
+            .insert("name".to_string(), Value::Str("Ada".to_string()));
+        let (lines, choices) = run_until_input(&mut runner);
+        assert_eq!(lines, vec!["Hello, Ada!", "Who are you?"]);
+        assert_eq!(choices, vec!["Pay 10 gold"]);
+
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        assert_eq!(
+            runner.advance().unwrap(),
+            Step::Line {
+                node: &dialogue["start"],
+                text: "Hello, {name}!".to_string()
+            }
+        );
+
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        runner.set_render_options(RenderOptions { strict: true });
+        assert_eq!(
+            runner.advance().unwrap_err().to_string(),
+            "Error in node 'start' on line 3: Unknown variable 'name'."
+        );
+    }
+
+    #[test]
+    fn test_runner_text_alternatives() {
+        let script = "::ask\n{~seq: Well?|Still here?|...}\n{~shuffle: a|b|c|d}\n* Ask again ({~cycle: 1|2}) => ask\n";
+        let dialogue = parse(script).unwrap();
+        let run = |seed: u64| {
+            let mut runner = DialogueRunner::new(&dialogue).unwrap();
+            runner.set_seed(seed);
+            let mut output = Vec::new();
+            for _ in 0..4 {
+                let (lines, choices) = run_until_input(&mut runner);
+                output.push((lines, choices));
+                runner.choose(0).unwrap();
+            }
+            output
+        };
+        let output = run(7);
+        let firsts: Vec<_> = output.iter().map(|(lines, _)| lines[0].as_str()).collect();
+        assert_eq!(firsts, vec!["Well?", "Still here?", "...", "..."]);
+        let choices: Vec<_> = output
+            .iter()
+            .map(|(_, choices)| choices[0].as_str())
+            .collect();
+        assert_eq!(
+            choices,
+            vec![
+                "Ask again (1)",
+                "Ask again (2)",
+                "Ask again (1)",
+                "Ask again (2)"
+            ]
+        );
+        assert!(output
+            .iter()
+            .all(|(lines, _)| ["a", "b", "c", "d"].contains(&lines[1].as_str())));
+        assert_eq!(run(7), output);
+    }
+
+    #[test]
+    fn test_runner_loops_and_counts_visits() {
+        let script = std::fs::read_to_string("examples/varion_long_example.vion").unwrap();
+        let dialogue = parse(&script).unwrap();
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        run_until_input(&mut runner);
+        runner.choose(1).unwrap();
+        for _ in 0..3 {
+            let (lines, choices) = run_until_input(&mut runner);
+            assert_eq!(lines, vec!["You ask for a reward."]);
+            assert_eq!(choices.len(), 3);
+            runner.choose(0).unwrap();
+        }
+        assert_eq!(runner.visit_count("ask_for_reward"), 3);
+    }
+
+    #[test]
+    fn test_runner_errors() {
+        let dialogue = parse("::start\n* Go => nowhere\n* Maybe => start @if missing\n").unwrap();
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        assert_eq!(runner.choose(0), Err(RunError::NotAwaitingChoice));
+        let err = runner.advance().unwrap_err();
+        assert!(matches!(
+            err,
+            RunError::Eval { error: EvalError::UnknownVariable(_), ref span, .. } if span.line == 3
+        ));
+
+        runner
+            .variables_mut()
+            .insert("missing".to_string(), Value::Bool(false));
+        runner.advance().unwrap();
+        assert_eq!(
+            runner.choose(1),
+            Err(RunError::InvalidChoice {
+                index: 1,
+                available: 1,
+            })
+        );
+        let err = runner.choose(0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Node 'nowhere' referenced from 'start' on line 2 does not exist."
+        );
+
+        assert_eq!(
+            DialogueRunner::new(&Dialogue::new()).unwrap_err(),
+            RunError::NoEntryNode
+        );
+        assert!(DialogueRunner::start_at(&dialogue, "missing").is_err());
+
+        let dialogue = parse("::start\n@action: set gold = base + 1\nHi.\n").unwrap();
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        assert!(runner.advance().is_err());
+        assert!(runner.advance().is_err());
+        runner
+            .variables_mut()
+            .insert("base".to_string(), Value::Int(1));
+        let (lines, _) = run_until_input(&mut runner);
+        assert_eq!(lines, vec!["Hi."]);
+        assert_eq!(runner.variables()["gold"], Value::Int(2));
+    }
+
+    #[test]
+    fn test_runner_stops_on_silent_cycles() {
+        let dialogue = parse("::a\n@next: a\n").unwrap();
+        assert!(dialogue.validate().is_ok());
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        let err = runner.advance().unwrap_err();
+        assert_eq!(
+            err,
+            RunError::NoProgress {
+                node: "a".to_string()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Node 'a' was reached again without producing any output."
+        );
+
+        let dialogue = parse("::a\n{~once: Hello}\n@next: b\n::b\n@next: a\n").unwrap();
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        assert_eq!(
+            runner.advance().unwrap(),
+            Step::Line {
+                node: &dialogue["a"],
+                text: "Hello".to_string(),
+            }
+        );
+        assert_eq!(
+            runner.advance(),
+            Err(RunError::NoProgress {
+                node: "b".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_runner_save_and_restore() {
+        let script = "::start\n@action: set gold = 5\nHello.\nHow are you?\n* Fine => end\n* Bad => end @if gold > 9\n\n::end\nBye.\n";
+        let dialogue = parse(script).unwrap();
+        let mut runner = DialogueRunner::new(&dialogue).unwrap();
+        runner.set_seed(3);
+        assert!(
+            matches!(runner.advance().unwrap(), Step::Line { ref text, .. } if text == "Hello.")
+        );
+
+        let snapshot = runner.state().to_snapshot();
+        let state = DialogueState::from_snapshot(&snapshot).unwrap();
+        let mut restored = DialogueRunner::restore(&dialogue, state).unwrap();
+        assert_eq!(restored.variables()["gold"], Value::Int(5));
+        assert_eq!(restored.state().seed(), 3);
+        assert_eq!(run_until_input(&mut restored), run_until_input(&mut runner));
+        assert_eq!(restored.state().pending_choices(), Some(&[0][..]));
+
+        let state = DialogueState::from_snapshot(&restored.state().to_snapshot()).unwrap();
+        let mut restored = DialogueRunner::restore(&dialogue, state).unwrap();
+        restored.choose(0).unwrap();
+        assert_eq!(run_until_input(&mut restored).0, vec!["Bye."]);
+        assert_eq!(restored.into_state().visit_count("start"), 1);
+
+        let edited = parse("::intro\nHi.\n").unwrap();
+        let state = DialogueState::from_snapshot(&snapshot).unwrap();
+        let err = DialogueRunner::restore(&edited, state).unwrap_err();
+        assert_eq!(
+            err,
+            StateError::NodeNotFound {
+                name: "start".to_string()
+            }
+        );
+
+        let shrunk = parse("::start\n* Fine => end\n::end\n").unwrap();
+        let mut state = DialogueState::new("start", 0);
+        state.phase = Phase::AwaitingChoice(vec![1]);
+        assert!(matches!(
+            DialogueRunner::restore(&shrunk, state),
+            Err(StateError::IncompatibleNode { .. })
+        ));
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+use crate::Value;
+
+/// The snapshot format version written by `DialogueState::to_snapshot`.
+pub const STATE_VERSION: u32 = 1;
+
+/// Where a conversation is within its current node.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Phase {
+    /// The node has been entered but not started.
+    Enter,
+    /// Running the node's actions; `next` is the next one to run.
+    Actions {
+        next: usize,
+    },
+    /// Yielding the node's remaining body lines.
+    Lines(VecDeque<String>),
+    /// Waiting for a choice; holds the indices of the presented choices.
+    AwaitingChoice(Vec<usize>),
+    Ended,
+}
+
+/// A small deterministic random number generator (SplitMix64), so that runs
+/// with the same seed make the same picks on every platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Everything needed to resume a conversation: the current node and the
+/// position within it, variables, visit counts, pending choices and the
+/// random number generator.
+///
+/// A state is tied to the `Dialogue` it was produced from only by node
+/// names, so it can be saved with `to_snapshot` and later restored with
+/// `DialogueRunner::restore`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DialogueState {
+    pub(crate) node: String,
+    pub(crate) phase: Phase,
+    pub(crate) variables: HashMap<String, Value>,
+    pub(crate) visits: HashMap<String, u32>,
+    pub(crate) seed: u64,
+    pub(crate) rng: Rng,
+}
+
+impl DialogueState {
+    /// Creates a state about to enter the named node.
+    pub fn new(node: &str, seed: u64) -> Self {
+        DialogueState {
+            node: node.to_string(),
+            phase: Phase::Enter,
+            variables: HashMap::new(),
+            visits: HashMap::new(),
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Returns the name of the current node.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    pub fn variables(&self) -> &HashMap<String, Value> {
+        &self.variables
+    }
+
+    pub fn variables_mut(&mut self) -> &mut HashMap<String, Value> {
+        &mut self.variables
+    }
+
+    /// Returns how many times the named node has been entered.
+    pub fn visit_count(&self, name: &str) -> u32 {
+        self.visits.get(name).copied().unwrap_or(0)
+    }
+
+    /// Returns the indices (into `Node::choices`) of the choices waiting
+    /// for an answer, if any.
+    pub fn pending_choices(&self) -> Option<&[usize]> {
+        match &self.phase {
+            Phase::AwaitingChoice(indices) => Some(indices),
+            _ => None,
+        }
+    }
+
+    /// Draws the next number from the state's random number generator.
+    ///
+    /// Hosts can use this for random picks that survive saving and loading.
+    pub fn random(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    /// Returns the seed the random number generator started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns `true` once the conversation is over.
+    pub fn is_ended(&self) -> bool {
+        self.phase == Phase::Ended
+    }
+
+    /// Serializes the state into a versioned, line-based text format.
+    ///
+    /// Variables and visit counts are written in name order, so equal states
+    /// always produce identical snapshots.
+    pub fn to_snapshot(&self) -> String {
+        let mut out = format!("varion-state {}\n", STATE_VERSION);
+        out.push_str(&format!("node {}\n", quote(&self.node)));
+        match &self.phase {
+            Phase::Enter => out.push_str("phase enter\n"),
+            Phase::Actions { next } => out.push_str(&format!("phase actions {}\n", next)),
+            Phase::Lines(lines) => {
+                out.push_str("phase lines\n");
+                for line in lines {
+                    out.push_str(&format!("line {}\n", quote(line)));
+                }
+            }
+            Phase::AwaitingChoice(indices) => {
+                out.push_str("phase choices");
+                for index in indices {
+                    out.push_str(&format!(" {}", index));
+                }
+                out.push('\n');
+            }
+            Phase::Ended => out.push_str("phase ended\n"),
+        }
+        out.push_str(&format!("seed {}\n", self.seed));
+        out.push_str(&format!("rng {}\n", self.rng.state));
+
+        let mut variables: Vec<_> = self.variables.iter().collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in variables {
+            let value = match value {
+                Value::Int(value) => format!("int {}", value),
+                Value::Float(value) => format!("float {:?}", value),
+                Value::Bool(value) => format!("bool {}", value),
+                Value::Str(value) => format!("str {}", quote(value)),
+            };
+            out.push_str(&format!("var {} {}\n", quote(name), value));
+        }
+
+        let mut visits: Vec<_> = self.visits.iter().collect();
+        visits.sort();
+        for (name, count) in visits {
+            out.push_str(&format!("visit {} {}\n", quote(name), count));
+        }
+        out
+    }
+
+    /// Reads a state written by `to_snapshot`.
+    pub fn from_snapshot(snapshot: &str) -> Result<Self, StateError> {
+        let mut lines = snapshot.lines().enumerate();
+        let version = match lines.next() {
+            Some((_, header)) => header
+                .strip_prefix("varion-state ")
+                .and_then(|version| version.trim().parse::<u32>().ok())
+                .ok_or_else(|| malformed(1, "Expected a 'varion-state <version>' header"))?,
+            None => return Err(malformed(1, "Empty snapshot")),
+        };
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut node = None;
+        let mut phase = None;
+        let mut pending_lines = VecDeque::new();
+        let mut seed = None;
+        let mut rng = None;
+        let mut variables = HashMap::new();
+        let mut visits = HashMap::new();
+
+        for (index, line) in lines {
+            let line_num = index + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_fields(line).map_err(|message| malformed(line_num, &message))?;
+            let field = |i: usize| {
+                fields
+                    .get(i)
+                    .map(String::as_str)
+                    .ok_or_else(|| malformed(line_num, "Missing field"))
+            };
+            let number = |i: usize| {
+                field(i)?
+                    .parse::<u64>()
+                    .map_err(|_| malformed(line_num, "Expected a number"))
+            };
+            match field(0)? {
+                "node" => node = Some(field(1)?.to_string()),
+                "phase" => {
+                    phase = Some(match field(1)? {
+                        "enter" => Phase::Enter,
+                        "actions" => Phase::Actions {
+                            next: number(2)? as usize,
+                        },
+                        "lines" => Phase::Lines(VecDeque::new()),
+                        "choices" => Phase::AwaitingChoice(
+                            (2..fields.len())
+                                .map(|i| number(i).map(|n| n as usize))
+                                .collect::<Result<_, _>>()?,
+                        ),
+                        "ended" => Phase::Ended,
+                        other => {
+                            return Err(malformed(line_num, &format!("Unknown phase '{}'", other)))
+                        }
+                    })
+                }
+                "line" => pending_lines.push_back(field(1)?.to_string()),
+                "seed" => seed = Some(number(1)?),
+                "rng" => rng = Some(number(1)?),
+                "var" => {
+                    let raw = field(3)?;
+                    let invalid = || malformed(line_num, &format!("Invalid value '{}'", raw));
+                    let value = match field(2)? {
+                        "int" => Value::Int(raw.parse().map_err(|_| invalid())?),
+                        "float" => Value::Float(raw.parse().map_err(|_| invalid())?),
+                        "bool" => Value::Bool(raw.parse().map_err(|_| invalid())?),
+                        "str" => Value::Str(raw.to_string()),
+                        other => {
+                            return Err(malformed(line_num, &format!("Unknown type '{}'", other)))
+                        }
+                    };
+                    variables.insert(field(1)?.to_string(), value);
+                }
+                "visit" => {
+                    let count = u32::try_from(number(2)?)
+                        .map_err(|_| malformed(line_num, "Visit count is too large"))?;
+                    visits.insert(field(1)?.to_string(), count);
+                }
+                other => {
+                    return Err(malformed(line_num, &format!("Unknown entry '{}'", other)));
+                }
+            }
+        }
+
+        let missing = |name: &str| malformed(0, &format!("Missing '{}' entry", name));
+        let mut phase = phase.ok_or_else(|| missing("phase"))?;
+        if let Phase::Lines(lines) = &mut phase {
+            *lines = pending_lines;
+        } else if !pending_lines.is_empty() {
+            return Err(malformed(
+                0,
+                "'line' entries are only allowed in the lines phase",
+            ));
+        }
+        Ok(DialogueState {
+            node: node.ok_or_else(|| missing("node"))?,
+            phase,
+            variables,
+            visits,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            rng: Rng {
+                state: rng.ok_or_else(|| missing("rng"))?,
+            },
+        })
+    }
+}
+
+/// An error produced while reading or restoring a saved state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The snapshot was written by an unknown format version.
+    UnsupportedVersion(u32),
+    /// The snapshot text is not valid. `line` is 1-based, or 0 when the
+    /// problem is not tied to a single line.
+    Malformed { line: usize, message: String },
+    /// The saved node no longer exists in the dialogue.
+    NodeNotFound { name: String },
+    /// The saved position does not fit the node as it is now, e.g. a
+    /// pending choice that was removed from the script.
+    IncompatibleNode { name: String, message: String },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported state version {}.", version)
+            }
+            StateError::Malformed { line: 0, message } => {
+                write!(f, "Malformed state: {}.", message)
+            }
+            StateError::Malformed { line, message } => {
+                write!(f, "Malformed state on line {}: {}.", line, message)
+            }
+            StateError::NodeNotFound { name } => {
+                write!(f, "Saved node '{}' does not exist in the dialogue.", name)
+            }
+            StateError::IncompatibleNode { name, message } => {
+                write!(
+                    f,
+                    "Saved position in node '{}' is no longer valid: {}.",
+                    name, message
+                )
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+fn malformed(line: usize, message: &str) -> StateError {
+    StateError::Malformed {
+        line,
+        message: message.to_string(),
+    }
+}
+
+/// Writes `text` as a double-quoted string with `\\`, `\"`, `\n`, `\r` and
+/// `\t` escapes.
+fn quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Splits a snapshot line into space-separated fields, unquoting quoted ones.
+fn split_fields(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ' ' {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut field = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => field.push('\n'),
+                        Some('r') => field.push('\r'),
+                        Some('t') => field.push('\t'),
+                        Some(c @ ('"' | '\\')) => field.push(c),
+                        _ => return Err("Invalid escape in quoted field".to_string()),
+                    },
+                    Some(c) => field.push(c),
+                    None => return Err("Unterminated quoted field".to_string()),
+                }
+            }
+            fields.push(field);
+        } else {
+            let mut field = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == ' ' {
+                    break;
+                }
+                field.push(c);
+                chars.next();
+            }
+            fields.push(field);
+        }
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut state = DialogueState::new("ask \"help\"", 42);
+        state.rng.next_u64();
+        state.phase = Phase::Lines(VecDeque::from([
+            "Hi\tthere".to_string(),
+            "Bye \\o/".to_string(),
+        ]));
+        state.variables.insert("gold".to_string(), Value::Int(-3));
+        state
+            .variables
+            .insert("ratio".to_string(), Value::Float(0.1));
+        state
+            .variables
+            .insert("helped".to_string(), Value::Bool(true));
+        state
+            .variables
+            .insert("name".to_string(), Value::from("Kim\nKyuRae"));
+        state.visits.insert("start".to_string(), 2);
+
+        let snapshot = state.to_snapshot();
+        assert!(snapshot.starts_with("varion-state 1\nnode \"ask \\\"help\\\"\"\nphase lines\n"));
+        assert!(snapshot.contains("var \"gold\" int -3\nvar \"helped\" bool true\n"));
+        let restored = DialogueState::from_snapshot(&snapshot).unwrap();
+        assert_eq!(restored, state);
+        assert_eq!(restored.to_snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_choices_phase() {
+        let mut state = DialogueState::new("start", 7);
+        state.phase = Phase::AwaitingChoice(vec![0, 2]);
+        let restored = DialogueState::from_snapshot(&state.to_snapshot()).unwrap();
+        assert_eq!(restored.pending_choices(), Some(&[0, 2][..]));
+        assert_eq!(restored.seed(), 7);
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        assert_eq!(
+            DialogueState::from_snapshot("varion-state 9\n"),
+            Err(StateError::UnsupportedVersion(9))
+        );
+        assert!(matches!(
+            DialogueState::from_snapshot("save file"),
+            Err(StateError::Malformed { line: 1, .. })
+        ));
+        let err =
+            DialogueState::from_snapshot("varion-state 1\nnode \"a\nphase enter\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Malformed state on line 2: Unterminated quoted field."
+        );
+        let err = DialogueState::from_snapshot("varion-state 1\nnode a\nphase enter\nseed 1\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Malformed state: Missing 'rng' entry.");
+        assert!(matches!(
+            DialogueState::from_snapshot("varion-state 1\nvar x int nope\n"),
+            Err(StateError::Malformed { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_rng_is_deterministic() {
+        let mut a = Rng::new(1);
+        let mut b = Rng::new(1);
+        let values: Vec<u64> = (0..3).map(|_| a.next_u64()).collect();
+        assert_eq!(values, (0..3).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(values[0], Rng::new(2).next_u64());
+    }
+}
@@ -0,0 +1,673 @@
+use crate::state::Rng;
+use crate::{evaluate, parse_expr, EvalError, Expr, ExprError, Value, VariableStore};
+
+/// A piece of body or choice text.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Segment {
+    /// Text shown as is, with `{{` and `}}` already unescaped.
+    Literal(String),
+    /// `{expr}`, replaced by the value of the expression.
+    Expr(Expr),
+    /// `{if condition}then{else}otherwise{end}`; `otherwise` is empty
+    /// without `{else}`.
+    Conditional {
+        condition: Expr,
+        then: Vec<Segment>,
+        otherwise: Vec<Segment>,
+    },
+    /// `{~kind: first|second|...}`, showing one of `options` depending on
+    /// the visit.
+    Alternatives {
+        kind: AlternativeKind,
+        options: Vec<Vec<Segment>>,
+    },
+}
+
+/// How `Segment::Alternatives` picks an option on the n-th visit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AlternativeKind {
+    /// `~seq`: the n-th option, then the last one from then on.
+    Sequence,
+    /// `~cycle`: the options in turn, starting over after the last.
+    Cycle,
+    /// `~once`: the n-th option, then nothing once they are used up.
+    Once,
+    /// `~shuffle`: a random option on every visit.
+    Shuffle,
+}
+
+impl AlternativeKind {
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "seq" => Some(AlternativeKind::Sequence),
+            "cycle" => Some(AlternativeKind::Cycle),
+            "once" => Some(AlternativeKind::Once),
+            "shuffle" => Some(AlternativeKind::Shuffle),
+            _ => None,
+        }
+    }
+
+    /// Returns the index of the option to show on the zero-based `visit`,
+    /// or `None` to show nothing.
+    fn pick(self, visit: u32, len: usize, rng: &mut Rng) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        let visit = visit as usize;
+        match self {
+            AlternativeKind::Sequence => Some(visit.min(len - 1)),
+            AlternativeKind::Cycle => Some(visit % len),
+            AlternativeKind::Once => (visit < len).then_some(visit),
+            AlternativeKind::Shuffle => Some((rng.next_u64() % len as u64) as usize),
+        }
+    }
+}
+
+/// Where text is being rendered, which `Segment::Alternatives` pick an
+/// option by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderContext {
+    /// How many times the node holding the text has been entered, counting
+    /// the current visit. `0` renders like the first visit.
+    pub visit: u32,
+    /// Seeds the picks of `~shuffle` alternatives. The same seed and visit
+    /// always give the same picks, so rendering is repeatable.
+    pub seed: u64,
+}
+
+/// Options for `render_text`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Fail on variables the store does not define. Otherwise they are left
+    /// in the text as `{name}`, and an `{if}` whose condition uses one shows
+    /// its `{else}` part, so a missing variable does not stop the dialogue.
+    pub strict: bool,
+}
+
+/// Splits text such as `Hello, {player_name}!` into segments.
+///
+/// `{expr}` is an expression, and `{if condition}...{else}...{end}` shows
+/// one of two parts; `{else}` is optional and conditionals nest.
+/// `{~seq: a|b}`, `{~cycle: a|b}`, `{~once: a|b}` and `{~shuffle: a|b}`
+/// are alternatives, whose options may hold any other text. `{{` and `}}`
+/// stand for literal braces; a lone `}` is kept as is outside
+/// alternatives. Adjacent literal text is merged into one segment. Error
+/// offsets are byte offsets into `source`.
+pub fn parse_text(source: &str) -> Result<Vec<Segment>, ExprError> {
+    let mut parser = TextParser { source, pos: 0 };
+    match parser.sequence(false)? {
+        (segments, Stop::Eof) => Ok(segments),
+        (_, stop) => Err(unexpected(stop)),
+    }
+}
+
+/// What ended a run of segments, with the span of the tag or character.
+enum Stop {
+    Eof,
+    Else(usize, usize),
+    End(usize, usize),
+    /// `|` between the options of an alternative.
+    Bar,
+    /// The `}` closing an alternative.
+    Close,
+}
+
+/// The error for an `{else}` or `{end}` that does not belong to an `{if}`.
+fn unexpected(stop: Stop) -> ExprError {
+    match stop {
+        Stop::Else(start, end) => error("'{else}' without '{if}'", start, end),
+        Stop::End(start, end) => error("'{end}' without '{if}'", start, end),
+        Stop::Eof | Stop::Bar | Stop::Close => unreachable!("not a tag"),
+    }
+}
+
+struct TextParser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl TextParser<'_> {
+    /// Parses segments up to the end of the text or an `{else}` or `{end}`
+    /// tag, which is consumed. In an alternative, `|` and `}` also end the
+    /// run.
+    fn sequence(&mut self, in_alternative: bool) -> Result<(Vec<Segment>, Stop), ExprError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let special: &[char] = if in_alternative {
+            &['{', '}', '|']
+        } else {
+            &['{', '}']
+        };
+        loop {
+            let rest = &self.source[self.pos..];
+            let Some(index) = rest.find(special) else {
+                literal.push_str(rest);
+                self.pos = self.source.len();
+                push_literal(&mut segments, literal);
+                return Ok((segments, Stop::Eof));
+            };
+            literal.push_str(&rest[..index]);
+            self.pos += index;
+            let brace = &rest[index..];
+            if brace.starts_with("{{") || brace.starts_with("}}") {
+                literal.push_str(&brace[..1]);
+                self.pos += 2;
+                continue;
+            }
+            if brace.starts_with(['}', '|']) {
+                self.pos += 1;
+                if !in_alternative {
+                    literal.push('}');
+                    continue;
+                }
+                push_literal(&mut segments, literal);
+                let stop = if brace.starts_with('|') {
+                    Stop::Bar
+                } else {
+                    Stop::Close
+                };
+                return Ok((segments, stop));
+            }
+
+            let open = self.pos;
+            if let Some(alternative) = brace.strip_prefix("{~") {
+                let segment = self.alternatives(alternative, open)?;
+                push_literal(&mut segments, std::mem::take(&mut literal));
+                segments.push(segment);
+                continue;
+            }
+            let Some(close) = closing_brace(&brace[1..]) else {
+                return Err(error("Unclosed '{'", open, self.source.len()));
+            };
+            let inner = &brace[1..1 + close];
+            let end = open + close + 2;
+            self.pos = end;
+            let trimmed = inner.trim_start();
+            let is_if = trimmed
+                .strip_prefix("if")
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace));
+            let segment = match inner.trim() {
+                "else" => {
+                    push_literal(&mut segments, literal);
+                    return Ok((segments, Stop::Else(open, end)));
+                }
+                "end" => {
+                    push_literal(&mut segments, literal);
+                    return Ok((segments, Stop::End(open, end)));
+                }
+                _ if is_if => {
+                    let offset = open + 1 + (inner.len() - trimmed.len()) + 2;
+                    self.conditional(&trimmed[2..], offset, (open, end))?
+                }
+                _ => Segment::Expr(expression(inner, open + 1, (open, end))?),
+            };
+            push_literal(&mut segments, std::mem::take(&mut literal));
+            segments.push(segment);
+        }
+    }
+
+    /// Parses the rest of a conditional after its `{if condition}` tag,
+    /// whose condition is `condition`, starting at `offset`.
+    fn conditional(
+        &mut self,
+        condition: &str,
+        offset: usize,
+        tag: (usize, usize),
+    ) -> Result<Segment, ExprError> {
+        if condition.trim().is_empty() {
+            return Err(error("Expected a condition after 'if'", tag.0, tag.1));
+        }
+        let condition = expression(condition, offset, tag)?;
+        let unclosed = || error("Unclosed '{if}'; expected '{end}'", tag.0, tag.1);
+        let (then, stop) = self.sequence(false)?;
+        let otherwise = match stop {
+            Stop::End(..) => Vec::new(),
+            Stop::Else(..) => match self.sequence(false)? {
+                (otherwise, Stop::End(..)) => otherwise,
+                (_, Stop::Else(start, end)) => {
+                    return Err(error("A second '{else}' in the same '{if}'", start, end))
+                }
+                _ => return Err(unclosed()),
+            },
+            _ => return Err(unclosed()),
+        };
+        Ok(Segment::Conditional {
+            condition,
+            then,
+            otherwise,
+        })
+    }
+
+    /// Parses an alternative whose `{~` starts at `open`; `rest` is the
+    /// text after the `{~`.
+    fn alternatives(&mut self, rest: &str, open: usize) -> Result<Segment, ExprError> {
+        let Some(colon) = rest.find(':') else {
+            return Err(error(
+                "Expected ':' after the alternative kind",
+                open,
+                self.source.len(),
+            ));
+        };
+        let keyword = rest[..colon].trim();
+        let tag_end = open + 2 + colon + 1;
+        let Some(kind) = AlternativeKind::from_keyword(keyword) else {
+            return Err(error(
+                &format!(
+                    "Unknown alternative kind '{}'; expected seq, cycle, once or shuffle",
+                    keyword
+                ),
+                open,
+                tag_end,
+            ));
+        };
+        let after = &rest[colon + 1..];
+        self.pos = tag_end + (after.len() - after.trim_start().len());
+        let mut options = Vec::new();
+        loop {
+            match self.sequence(true)? {
+                (option, Stop::Bar) => options.push(option),
+                (option, Stop::Close) => {
+                    options.push(option);
+                    return Ok(Segment::Alternatives { kind, options });
+                }
+                (_, Stop::Eof) => return Err(error("Unclosed '{~'; expected '}'", open, tag_end)),
+                (_, stop) => return Err(unexpected(stop)),
+            }
+        }
+    }
+}
+
+/// Parses the expression `source`, which starts at `offset` in the text
+/// and sits in the tag spanning `tag`.
+fn expression(source: &str, offset: usize, tag: (usize, usize)) -> Result<Expr, ExprError> {
+    parse_expr(source).map_err(|err| {
+        // An empty expression is reported on the whole tag.
+        if source.trim().is_empty() {
+            error(&err.message, tag.0, tag.1)
+        } else {
+            error(&err.message, offset + err.start, offset + err.end)
+        }
+    })
+}
+
+fn push_literal(segments: &mut Vec<Segment>, literal: String) {
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+}
+
+fn error(message: &str, start: usize, end: usize) -> ExprError {
+    ExprError {
+        message: message.to_string(),
+        start,
+        end,
+    }
+}
+
+/// Returns the offset of the `}` that ends a tag, skipping quoted strings.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '}') => return Some(index),
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+/// Fills in the expression segments from `vars`, picks a part of each
+/// conditional and an option of each alternative, and joins the result.
+pub fn render_text(
+    segments: &[Segment],
+    vars: &impl VariableStore,
+    context: &RenderContext,
+    options: &RenderOptions,
+) -> Result<String, EvalError> {
+    let mut renderer = Renderer {
+        out: String::new(),
+        vars,
+        visit: context.visit.saturating_sub(1),
+        rng: Rng::new(context.seed ^ u64::from(context.visit).rotate_left(32)),
+        options,
+    };
+    renderer.render(segments)?;
+    Ok(renderer.out)
+}
+
+struct Renderer<'a, V> {
+    out: String,
+    vars: &'a V,
+    /// The zero-based visit.
+    visit: u32,
+    rng: Rng,
+    options: &'a RenderOptions,
+}
+
+impl<V: VariableStore> Renderer<'_, V> {
+    fn render(&mut self, segments: &[Segment]) -> Result<(), EvalError> {
+        for segment in segments {
+            match segment {
+                Segment::Literal(text) => self.out.push_str(text),
+                Segment::Expr(expr) => match evaluate(expr, self.vars) {
+                    Ok(value) => self.out.push_str(&value.to_string()),
+                    Err(EvalError::UnknownVariable(_)) if !self.options.strict => {
+                        self.out.push_str(&format!("{{{}}}", expr))
+                    }
+                    Err(err) => return Err(err),
+                },
+                Segment::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    let holds = match evaluate(condition, self.vars) {
+                        Ok(Value::Bool(value)) => value,
+                        Ok(other) => {
+                            return Err(EvalError::ConditionNotBool {
+                                found: other.type_name(),
+                            })
+                        }
+                        Err(EvalError::UnknownVariable(_)) if !self.options.strict => false,
+                        Err(err) => return Err(err),
+                    };
+                    self.render(if holds { then } else { otherwise })?;
+                }
+                Segment::Alternatives { kind, options } => {
+                    if let Some(index) = kind.pick(self.visit, options.len(), &mut self.rng) {
+                        self.render(&options[index])?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BinaryOp;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_text() {
+        assert_eq!(
+            parse_text("Hi {name}, {{braces}} } {gold * 2}!").unwrap(),
+            vec![
+                Segment::Literal("Hi ".to_string()),
+                Segment::Expr(Expr::Variable("name".to_string())),
+                Segment::Literal(", {braces} } ".to_string()),
+                Segment::Expr(Expr::Binary {
+                    op: BinaryOp::Mul,
+                    left: Box::new(Expr::Variable("gold".to_string())),
+                    right: Box::new(Expr::Literal(Value::Int(2))),
+                }),
+                Segment::Literal("!".to_string()),
+            ]
+        );
+        assert_eq!(parse_text("").unwrap(), Vec::new());
+        assert_eq!(
+            parse_text("{\"}\" + x}").unwrap().len(),
+            1,
+            "a brace inside a string does not close the expression"
+        );
+
+        let err = parse_text("Hi {name").unwrap_err();
+        assert_eq!(
+            (err.message.as_str(), err.start, err.end),
+            ("Unclosed '{'", 3, 8)
+        );
+        let err = parse_text("a {} b").unwrap_err();
+        assert_eq!((err.start, err.end), (2, 4));
+        let err = parse_text("a {1 +} b").unwrap_err();
+        assert_eq!(err.start, 6);
+    }
+
+    #[test]
+    fn test_parse_conditionals() {
+        let variable = |name: &str| Expr::Variable(name.to_string());
+        let literal = |text: &str| Segment::Literal(text.to_string());
+        assert_eq!(
+            parse_text("{if helped}Thanks, {name}!{else}Who are you?{end} Bye.").unwrap(),
+            vec![
+                Segment::Conditional {
+                    condition: variable("helped"),
+                    then: vec![
+                        literal("Thanks, "),
+                        Segment::Expr(variable("name")),
+                        literal("!")
+                    ],
+                    otherwise: vec![literal("Who are you?")],
+                },
+                literal(" Bye."),
+            ]
+        );
+        assert_eq!(
+            parse_text("{ if a }{if b}x{end}{ end }").unwrap(),
+            vec![Segment::Conditional {
+                condition: variable("a"),
+                then: vec![Segment::Conditional {
+                    condition: variable("b"),
+                    then: vec![literal("x")],
+                    otherwise: Vec::new(),
+                }],
+                otherwise: Vec::new(),
+            }]
+        );
+        assert_eq!(
+            parse_text("{iffy}").unwrap(),
+            vec![Segment::Expr(variable("iffy"))]
+        );
+
+        let errors = [
+            ("{if a}open", "Unclosed '{if}'; expected '{end}'", 0, 6),
+            ("a{end}", "'{end}' without '{if}'", 1, 6),
+            ("{else}", "'{else}' without '{if}'", 0, 6),
+            (
+                "{if a}x{else}y{else}z{end}",
+                "A second '{else}' in the same '{if}'",
+                14,
+                20,
+            ),
+            ("{if }x{end}", "Expected a condition after 'if'", 0, 5),
+        ];
+        for (source, message, start, end) in errors {
+            let err = parse_text(source).unwrap_err();
+            assert_eq!(
+                (err.message.as_str(), err.start, err.end),
+                (message, start, end),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(parse_text("{if a >}x{end}").unwrap_err().start, 7);
+    }
+
+    #[test]
+    fn test_parse_alternatives() {
+        let literal = |text: &str| Segment::Literal(text.to_string());
+        assert_eq!(
+            parse_text("{~seq: Hi {name}|{if a}x|y{end}|} end").unwrap(),
+            vec![
+                Segment::Alternatives {
+                    kind: AlternativeKind::Sequence,
+                    options: vec![
+                        vec![
+                            literal("Hi "),
+                            Segment::Expr(Expr::Variable("name".to_string()))
+                        ],
+                        vec![Segment::Conditional {
+                            condition: Expr::Variable("a".to_string()),
+                            then: vec![literal("x|y")],
+                            otherwise: Vec::new(),
+                        }],
+                        Vec::new(),
+                    ],
+                },
+                literal(" end"),
+            ]
+        );
+        assert_eq!(parse_text("a|b}").unwrap(), vec![literal("a|b}")]);
+
+        let errors = [
+            (
+                "{~seq a|b}",
+                "Expected ':' after the alternative kind",
+                0,
+                10,
+            ),
+            (
+                "{~random: a}",
+                "Unknown alternative kind 'random'; expected seq, cycle, once or shuffle",
+                0,
+                9,
+            ),
+            ("{~once: a|b", "Unclosed '{~'; expected '}'", 0, 7),
+            ("{~cycle: a{end}}", "'{end}' without '{if}'", 10, 15),
+        ];
+        for (source, message, start, end) in errors {
+            let err = parse_text(source).unwrap_err();
+            assert_eq!(
+                (err.message.as_str(), err.start, err.end),
+                (message, start, end),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_alternatives() {
+        let vars = HashMap::new();
+        let render = |source: &str, visit: u32, seed: u64| {
+            let context = RenderContext { visit, seed };
+            render_text(
+                &parse_text(source).unwrap(),
+                &vars,
+                &context,
+                &RenderOptions::default(),
+            )
+            .unwrap()
+        };
+        let visits = |source: &str| -> Vec<String> {
+            (1..=4).map(|visit| render(source, visit, 0)).collect()
+        };
+        assert_eq!(visits("{~seq: a|b|c}"), ["a", "b", "c", "c"]);
+        assert_eq!(visits("{~cycle: a|b|c}"), ["a", "b", "c", "a"]);
+        assert_eq!(visits("{~once: a|b|c}"), ["a", "b", "c", ""]);
+        assert_eq!(render("{~seq: a|b}", 0, 0), "a");
+
+        for kind in [
+            AlternativeKind::Sequence,
+            AlternativeKind::Cycle,
+            AlternativeKind::Once,
+            AlternativeKind::Shuffle,
+        ] {
+            let empty = [Segment::Alternatives {
+                kind,
+                options: Vec::new(),
+            }];
+            let text = render_text(
+                &empty,
+                &vars,
+                &RenderContext::default(),
+                &RenderOptions::default(),
+            );
+            assert_eq!(text, Ok(String::new()));
+        }
+
+        let shuffle = "{~shuffle: a|b|c|d|e|f|g|h}";
+        let picks: Vec<String> = (1..=20).map(|visit| render(shuffle, visit, 42)).collect();
+        assert_eq!(
+            picks,
+            (1..=20)
+                .map(|visit| render(shuffle, visit, 42))
+                .collect::<Vec<_>>()
+        );
+        assert!(picks.iter().any(|pick| pick != &picks[0]));
+        assert!((0..20).any(|seed| render(shuffle, 1, seed) != render(shuffle, 1, 0)));
+    }
+
+    #[test]
+    fn test_render_text() {
+        let vars: HashMap<String, Value> = [
+            ("name".to_string(), Value::Str("Ada".to_string())),
+            ("gold".to_string(), Value::Int(21)),
+        ]
+        .into_iter()
+        .collect();
+        let segments = parse_text("{name} has {gold * 2} gold{{s}} and {title}.").unwrap();
+        assert_eq!(
+            render_text(
+                &segments,
+                &vars,
+                &RenderContext::default(),
+                &RenderOptions::default()
+            )
+            .unwrap(),
+            "Ada has 42 gold{s} and {title}."
+        );
+        assert_eq!(
+            render_text(
+                &segments,
+                &vars,
+                &RenderContext::default(),
+                &RenderOptions { strict: true }
+            ),
+            Err(EvalError::UnknownVariable("title".to_string()))
+        );
+        let segments =
+            parse_text("{if gold > 20}Rich{else}Poor{end}, {if helped}thanks{end}.").unwrap();
+        assert_eq!(
+            render_text(
+                &segments,
+                &vars,
+                &RenderContext::default(),
+                &RenderOptions::default()
+            )
+            .unwrap(),
+            "Rich, ."
+        );
+        assert_eq!(
+            render_text(
+                &segments,
+                &vars,
+                &RenderContext::default(),
+                &RenderOptions { strict: true }
+            ),
+            Err(EvalError::UnknownVariable("helped".to_string()))
+        );
+        let segments = parse_text("{if gold}x{end}").unwrap();
+        assert_eq!(
+            render_text(
+                &segments,
+                &vars,
+                &RenderContext::default(),
+                &RenderOptions::default()
+            ),
+            Err(EvalError::ConditionNotBool { found: "int" })
+        );
+        let segments = parse_text("{gold / 0}").unwrap();
+        assert_eq!(
+            render_text(
+                &segments,
+                &vars,
+                &RenderContext::default(),
+                &RenderOptions::default()
+            ),
+            Err(EvalError::DivisionByZero)
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::namespace::candidates;
+use crate::{Dialogue, Span};
+
+/// A broken reference found by `Dialogue::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A choice whose `target_node` does not name an existing node.
+    UnresolvedTarget {
+        node: String,
+        /// Index of the choice in `Node::choices`.
+        choice: usize,
+        target: String,
+        span: Span,
+        /// The closest existing node name, if one is similar enough.
+        suggestion: Option<String>,
+        /// The node names that were looked up, in order; more than one when
+        /// the reference was written inside a `@namespace`.
+        searched: Vec<String>,
+    },
+    /// An `@next` directive that does not name an existing node.
+    UnresolvedNext {
+        node: String,
+        target: String,
+        span: Span,
+        suggestion: Option<String>,
+        searched: Vec<String>,
+    },
+    /// An `@start` directive that does not name an existing node.
+    UnresolvedStart {
+        target: String,
+        span: Span,
+        suggestion: Option<String>,
+        searched: Vec<String>,
+    },
+}
+
+impl ValidationError {
+    /// Returns the location of the unresolved name.
+    pub fn span(&self) -> Span {
+        match self {
+            ValidationError::UnresolvedTarget { span, .. }
+            | ValidationError::UnresolvedNext { span, .. }
+            | ValidationError::UnresolvedStart { span, .. } => *span,
+        }
+    }
+
+    /// Returns the closest existing node name, if any.
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            ValidationError::UnresolvedTarget { suggestion, .. }
+            | ValidationError::UnresolvedNext { suggestion, .. }
+            | ValidationError::UnresolvedStart { suggestion, .. } => suggestion.as_deref(),
+        }
+    }
+
+    /// Returns the error message without location information.
+    pub fn message(&self) -> String {
+        let mut message = match self {
+            ValidationError::UnresolvedTarget { node, target, .. } => {
+                format!(
+                    "Choice in node '{}' targets unknown node '{}'.",
+                    node, target
+                )
+            }
+            ValidationError::UnresolvedNext { node, target, .. } => {
+                format!(
+                    "@next in node '{}' refers to unknown node '{}'.",
+                    node, target
+                )
+            }
+            ValidationError::UnresolvedStart { target, .. } => {
+                format!("@start refers to unknown node '{}'.", target)
+            }
+        };
+        let (ValidationError::UnresolvedTarget { searched, .. }
+        | ValidationError::UnresolvedNext { searched, .. }
+        | ValidationError::UnresolvedStart { searched, .. }) = self;
+        if searched.len() > 1 {
+            let searched: Vec<String> = searched.iter().map(|name| format!("'{}'", name)).collect();
+            message.pop();
+            message.push_str(&format!(" (searched {}).", searched.join(", ")));
+        }
+        if let Some(suggestion) = self.suggestion() {
+            message.push_str(&format!(" Did you mean '{}'?", suggestion));
+        }
+        message
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(
+            f,
+            "Error on line {}, column {}: {}",
+            span.line,
+            span.column,
+            self.message()
+        )
+    }
+}
+
+impl Error for ValidationError {}
+
+impl Dialogue {
+    /// Checks that every choice target, `@next` and `@start` directive names
+    /// an existing node.
+    ///
+    /// Returns every unresolved reference, `@start` first and then node by
+    /// node in declaration order, each with a "did you mean" suggestion when
+    /// an existing node name is within a small edit distance.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        if let Some(start) = &self.start {
+            let namespace = self.start_namespace.as_deref();
+            if self.resolve(namespace, start).is_none() {
+                errors.push(ValidationError::UnresolvedStart {
+                    target: start.clone(),
+                    span: self.start_span.unwrap_or_default(),
+                    suggestion: suggest(start, &self.reachable_names(namespace)),
+                    searched: candidates(namespace, start),
+                });
+            }
+        }
+        for node in self.iter() {
+            let namespace = node.namespace.as_deref();
+            let names = || self.reachable_names(namespace);
+            for (index, choice) in node.choices.iter().enumerate() {
+                if self.resolve_from(node, &choice.target_node).is_none() {
+                    errors.push(ValidationError::UnresolvedTarget {
+                        node: node.name.clone(),
+                        choice: index,
+                        target: choice.target_node.clone(),
+                        span: choice.target_span,
+                        suggestion: suggest(&choice.target_node, &names()),
+                        searched: candidates(namespace, &choice.target_node),
+                    });
+                }
+            }
+            if let Some(next) = &node.next {
+                if self.resolve_from(node, next).is_none() {
+                    errors.push(ValidationError::UnresolvedNext {
+                        node: node.name.clone(),
+                        target: next.clone(),
+                        span: node.spans.next.unwrap_or_default(),
+                        suggestion: suggest(next, &names()),
+                        searched: candidates(namespace, next),
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Dialogue {
+    /// The names that resolve from `namespace`: every full name, and the
+    /// local names of the nodes in `namespace`. Sorted, so suggestions do not
+    /// depend on hash order.
+    fn reachable_names(&self, namespace: Option<&str>) -> Vec<&str> {
+        let mut names: Vec<&str> = self.nodes.keys().map(String::as_str).collect();
+        if namespace.is_some() {
+            names.extend(
+                self.iter()
+                    .filter(|node| node.namespace.as_deref() == namespace)
+                    .map(|node| node.local_name()),
+            );
+        }
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Picks the candidate closest to `name`, if it is close enough to be a
+/// plausible typo.
+fn suggest(name: &str, candidates: &[&str]) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("start", "start"), 0);
+        assert_eq!(edit_distance("시작", "시작점"), 1);
+    }
+
+    #[test]
+    fn test_validate_reports_unresolved_references() {
+        let script = r#"
+::start
+* Go => nowhere
+* Help => ask_hepl
+* Fine => end
+
+::ask_help
+@next: typo_node
+
+::end
+The end.
+"#;
+        let dialogue = parse(script).unwrap();
+        let errors = dialogue.validate().unwrap_err();
+        assert_eq!(errors.len(), 3);
+
+        assert!(matches!(
+            &errors[0],
+            ValidationError::UnresolvedTarget { choice: 0, target, suggestion: None, .. }
+                if target == "nowhere"
+        ));
+        assert_eq!(errors[1].suggestion(), Some("ask_help"));
+        assert_eq!(
+            errors[1].to_string(),
+            "Error on line 4, column 11: Choice in node 'start' targets unknown node 'ask_hepl'. Did you mean 'ask_help'?"
+        );
+        assert_eq!(
+            errors[2],
+            ValidationError::UnresolvedNext {
+                node: "ask_help".to_string(),
+                target: "typo_node".to_string(),
+                span: Span {
+                    line: 8,
+                    column: 8,
+                    start: 77,
+                    end: 86
+                },
+                suggestion: None,
+                searched: vec!["typo_node".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_start_directive() {
+        let dialogue = parse("@start: intr\n::intro\nHi\n").unwrap();
+        let errors = dialogue.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ValidationError::UnresolvedStart {
+                target: "intr".to_string(),
+                span: Span {
+                    line: 1,
+                    column: 9,
+                    start: 8,
+                    end: 12
+                },
+                suggestion: Some("intro".to_string()),
+                searched: vec!["intr".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_examples() {
+        for path in [
+            "examples/varion_examples.va",
+            "examples/varion_long_example.vion",
+        ] {
+            let script = std::fs::read_to_string(path).unwrap();
+            assert_eq!(parse(&script).unwrap().validate(), Ok(()));
+        }
+    }
+}